@@ -0,0 +1,49 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use raytracer::camera::Camera;
+use raytracer::vec3::Vec3;
+use raytracer::settings::RenderSettings;
+use raytracer::{ random_scene, raytrace_buffer };
+
+const IMAGE_WIDTH: usize = 60;
+const IMAGE_HEIGHT: usize = 40;
+const SAMPLES_PER_PIXEL: usize = 1;
+const MAX_DEPTH: usize = 10;
+
+fn camera() -> Camera {
+    let aspect_ratio = IMAGE_WIDTH as f64 / IMAGE_HEIGHT as f64;
+    let lookfrom = Vec3::new(13., 2., 3.);
+    let lookat = Vec3::new(0., 0., 0.);
+    let vup = Vec3::new(0., 1., 0.);
+
+    Camera::new(lookfrom, lookat, vup, 20., aspect_ratio, 0.1, 10.)
+}
+
+// Renders a small image of random_scene with and without the BVH, the grid
+// of small spheres grows quadratically with the scene size
+fn traversal(c: &mut Criterion) {
+    let camera = camera();
+    let settings = RenderSettings::new(IMAGE_WIDTH, IMAGE_HEIGHT, SAMPLES_PER_PIXEL, MAX_DEPTH);
+    let mut group = c.benchmark_group("random_scene");
+    group.sample_size(10);
+
+    for size in [2, 5, 10, 20] {
+        let mut world = random_scene(size);
+        let objects = world.len();
+
+        group.bench_with_input(BenchmarkId::new("linear", objects), &world, |b, world| {
+            b.iter(|| raytrace_buffer(&settings, world, &camera, &()))
+        });
+
+        world.build_bvh();
+
+        group.bench_with_input(BenchmarkId::new("bvh", objects), &world, |b, world| {
+            b.iter(|| raytrace_buffer(&settings, world, &camera, &()))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, traversal);
+criterion_main!(benches);
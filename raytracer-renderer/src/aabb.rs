@@ -0,0 +1,91 @@
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+// Axis-aligned bounding box
+#[derive(Clone, Copy)]
+pub struct Aabb {
+    minimum: Vec3,
+    maximum: Vec3,
+}
+
+impl Aabb {
+    pub fn new(minimum: Vec3, maximum: Vec3) -> Aabb {
+        Aabb {
+            minimum,
+            maximum,
+        }
+    }
+
+    // Box that contains nothing, identity for surrounding_box
+    pub fn empty() -> Aabb {
+        Aabb {
+            minimum: Vec3::constant_new(f64::INFINITY),
+            maximum: Vec3::constant_new(f64::NEG_INFINITY),
+        }
+    }
+
+    pub fn minimum(&self) -> Vec3 {
+        self.minimum
+    }
+
+    pub fn maximum(&self) -> Vec3 {
+        self.maximum
+    }
+
+    pub fn surrounding_box(a: &Aabb, b: &Aabb) -> Aabb {
+        Aabb {
+            minimum: Vec3::min(&a.minimum, &b.minimum),
+            maximum: Vec3::max(&a.maximum, &b.maximum),
+        }
+    }
+
+    pub fn centroid(&self) -> Vec3 {
+        (self.minimum + self.maximum) * 0.5
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let extent = self.maximum - self.minimum;
+        if extent.x() < 0. || extent.y() < 0. || extent.z() < 0. {
+            return 0.;
+        }
+
+        2. * (extent.x() * extent.y() + extent.y() * extent.z() + extent.z() * extent.x())
+    }
+
+    // Index of the axis with the largest extent
+    pub fn longest_axis(&self) -> usize {
+        let extent = self.maximum - self.minimum;
+        if extent.x() > extent.y() && extent.x() > extent.z() {
+            0
+        } else if extent.y() > extent.z() {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        let direction = ray.direction();
+        let inv_direction = Vec3::new(1. / direction.x(), 1. / direction.y(), 1. / direction.z());
+        self.intersects(ray.origin(), inv_direction, t_min, t_max)
+    }
+
+    // Slab test with the reciprocal of the ray direction precomputed by the caller
+    pub(crate) fn intersects(&self, origin: Vec3, inv_direction: Vec3, mut t_min: f64, mut t_max: f64) -> bool {
+        for axis in 0..3 {
+            let t0 = (self.minimum[axis] - origin[axis]) * inv_direction[axis];
+            let t1 = (self.maximum[axis] - origin[axis]) * inv_direction[axis];
+            let (t0, t1) = if inv_direction[axis] < 0. { (t1, t0) } else { (t0, t1) };
+
+            // Written so that NaN (0 * inf) never shrinks the interval
+            t_min = if t0 > t_min { t0 } else { t_min };
+            t_max = if t1 < t_max { t1 } else { t_max };
+
+            if t_max < t_min {
+                return false;
+            }
+        }
+
+        true
+    }
+}
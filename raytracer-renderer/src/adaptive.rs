@@ -0,0 +1,156 @@
+use crate::vec3::Vec3;
+
+use serde::{ Serialize, Deserialize };
+
+// z value of a 95% confidence interval
+const CONFIDENCE_Z: f64 = 1.96;
+
+// Pixels darker than this are judged as if they had this luminance, so the
+// relative error of nearly black pixels does not keep them sampling forever
+const DARK_LUMINANCE: f64 = 0.05;
+
+// Stops sampling pixels once their estimate is good enough. samples_per_pixel
+// of the render settings becomes the most samples a pixel gets.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdaptiveSampling {
+    // A pixel is done once the 95% confidence interval of its mean luminance
+    // is narrower than this fraction of the mean
+    pub noise_threshold: f64,
+    // Samples every pixel gets before it is tested, at most samples_per_pixel
+    pub min_samples: usize,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        AdaptiveSampling {
+            noise_threshold: 0.05,
+            min_samples: 16,
+        }
+    }
+}
+
+// Running mean of the samples of one pixel and the variance of their
+// luminance, updated with Welford's algorithm
+#[derive(Clone, Copy)]
+pub struct PixelStatistics {
+    count: usize,
+    // Samples counted but left out of the mean
+    skipped: usize,
+    mean: Vec3,
+    mean_luminance: f64,
+    // Sum of squared differences from the mean luminance
+    luminance_m2: f64,
+}
+
+impl Default for PixelStatistics {
+    fn default() -> Self {
+        PixelStatistics {
+            count: 0,
+            skipped: 0,
+            mean: Vec3::constant_new(0.),
+            mean_luminance: 0.,
+            luminance_m2: 0.,
+        }
+    }
+}
+
+impl PixelStatistics {
+    pub fn add(&mut self, color: Vec3) {
+        self.count += 1;
+        let count = self.added() as f64;
+
+        self.mean = self.mean + (color - self.mean) / count;
+
+        let luminance = luminance(color);
+        let delta = luminance - self.mean_luminance;
+        self.mean_luminance += delta / count;
+        self.luminance_m2 += delta * (luminance - self.mean_luminance);
+    }
+
+    // Takes a sample without adding it, so the pixel moves on to the next one
+    pub fn skip(&mut self) {
+        self.count += 1;
+        self.skipped += 1;
+    }
+
+    // Samples taken, skipped ones included
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    fn added(&self) -> usize {
+        self.count - self.skipped
+    }
+
+    pub fn mean(&self) -> Vec3 {
+        self.mean
+    }
+
+    // Half width of the confidence interval of the mean luminance
+    pub fn error(&self) -> f64 {
+        let added = self.added();
+        if added < 2 {
+            return f64::INFINITY;
+        }
+
+        let variance = self.luminance_m2 / (added - 1) as f64;
+        CONFIDENCE_Z * (variance / added as f64).sqrt()
+    }
+
+    pub fn is_converged(&self, adaptive: &AdaptiveSampling) -> bool {
+        self.count >= adaptive.min_samples.max(2)
+            && self.error() <= adaptive.noise_threshold * f64::max(self.mean_luminance, DARK_LUMINANCE)
+    }
+}
+
+// Rec. 709 weights
+fn luminance(color: Vec3) -> f64 {
+    0.2126 * color.x() + 0.7152 * color.y() + 0.0722 * color.z()
+}
+
+// Colors each pixel by how many samples it took, from dark blue for
+// min_samples over green to red for max_samples
+pub fn sample_heatmap(sample_counts: &[usize], min_samples: usize, max_samples: usize) -> Vec<u8> {
+    let range = max_samples.saturating_sub(min_samples).max(1) as f64;
+
+    sample_counts
+        .iter()
+        .flat_map(|&count| {
+            let t = (count.saturating_sub(min_samples) as f64 / range).min(1.);
+            let (from, to, t) = if t < 0.5 {
+                (Vec3::new(0., 0., 0.5), Vec3::new(0., 1., 0.), t * 2.)
+            } else {
+                (Vec3::new(0., 1., 0.), Vec3::new(1., 0., 0.), t * 2. - 1.)
+            };
+            let color = from * (1. - t) + to * t;
+
+            [color.x(), color.y(), color.z()].map(|channel| (channel * 255.).round() as u8)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skipped_samples_count_but_stay_out_of_the_mean() {
+        let mut statistics = PixelStatistics::default();
+        statistics.add(Vec3::new(1., 2., 3.));
+        statistics.skip();
+        statistics.add(Vec3::new(3., 2., 1.));
+
+        assert_eq!(statistics.count(), 3);
+        let mean = statistics.mean();
+        assert_eq!([mean.x(), mean.y(), mean.z()], [2.; 3]);
+        assert!(statistics.error().is_finite());
+
+        let mut skipped = PixelStatistics::default();
+        skipped.skip();
+        skipped.skip();
+        let mean = skipped.mean();
+        assert_eq!([mean.x(), mean.y(), mean.z()], [0.; 3]);
+        assert_eq!(skipped.error(), f64::INFINITY);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::vec3::Vec3;
+use crate::aabb::Aabb;
+use crate::transform::Transform;
+
+// Times sampled per keyframe interval when bounding an animated box
+const BOX_SAMPLES: usize = 32;
+
+// Pose at one point in time. Poses are interpolated part by part, so it is
+// scaled first, then rotated and then translated.
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    time: f64,
+    scale: Vec3,
+    rotation: Quaternion,
+    translation: Vec3,
+}
+
+impl Keyframe {
+    // Identity pose
+    pub fn new(time: f64) -> Keyframe {
+        Keyframe {
+            time,
+            scale: Vec3::constant_new(1.),
+            rotation: Quaternion::IDENTITY,
+            translation: Vec3::constant_new(0.),
+        }
+    }
+
+    pub fn with_scale(mut self, factors: Vec3) -> Keyframe {
+        self.scale = factors;
+        self
+    }
+
+    // Counterclockwise around axis like Transform::rotation. Between two
+    // keyframes objects turn the short way, by at most 180 degrees.
+    pub fn with_rotation(mut self, axis: Vec3, degrees: f64) -> Keyframe {
+        self.rotation = Quaternion::from_axis_angle(axis, degrees);
+        self
+    }
+
+    pub fn with_translation(mut self, offset: Vec3) -> Keyframe {
+        self.translation = offset;
+        self
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    fn transform(&self) -> Transform {
+        Transform::translation(self.translation) * self.rotation.to_transform() * Transform::scaling(self.scale)
+    }
+
+    fn interpolate(a: &Keyframe, b: &Keyframe, time: f64) -> Keyframe {
+        let fraction = (time - a.time) / (b.time - a.time);
+
+        Keyframe {
+            time,
+            scale: a.scale + (b.scale - a.scale) * fraction,
+            rotation: Quaternion::slerp(&a.rotation, &b.rotation, fraction),
+            translation: a.translation + (b.translation - a.translation) * fraction,
+        }
+    }
+}
+
+// Transform that changes over time, holding still before the first and after
+// the last keyframe
+pub struct KeyframedTransform {
+    // Sorted by time
+    keyframes: Vec<Keyframe>,
+}
+
+impl KeyframedTransform {
+    pub fn new(mut keyframes: Vec<Keyframe>) -> KeyframedTransform {
+        assert!(!keyframes.is_empty(), "an animation needs at least one keyframe");
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        assert!(keyframes.windows(2).all(|pair| pair[0].time < pair[1].time), "keyframe times must all differ");
+
+        KeyframedTransform {
+            keyframes,
+        }
+    }
+
+    pub fn at(&self, time: f64) -> Transform {
+        let next = self.keyframes.partition_point(|keyframe| keyframe.time <= time);
+
+        if next == 0 {
+            self.keyframes[0].transform()
+        } else if next == self.keyframes.len() {
+            self.keyframes[next - 1].transform()
+        } else {
+            Keyframe::interpolate(&self.keyframes[next - 1], &self.keyframes[next], time).transform()
+        }
+    }
+
+    // Box around aabb over the whole animation. Sampled, so it is padded by
+    // how far a rotating corner can stray from the chord between samples.
+    pub fn apply_box(&self, aabb: &Aabb) -> Aabb {
+        let mut swept = self.keyframes[0].transform().apply_box(aabb);
+        let mut max_step_angle: f64 = 0.;
+
+        for pair in self.keyframes.windows(2) {
+            let (a, b) = (&pair[0], &pair[1]);
+            for sample in 1..=BOX_SAMPLES {
+                let time = a.time + (b.time - a.time) * sample as f64 / BOX_SAMPLES as f64;
+                let keyframe = Keyframe::interpolate(a, b, time);
+                swept = Aabb::surrounding_box(&swept, &keyframe.transform().apply_box(aabb));
+            }
+
+            max_step_angle = max_step_angle.max(Quaternion::angle_between(&a.rotation, &b.rotation) / BOX_SAMPLES as f64);
+        }
+
+        let reach = (swept.maximum() - swept.minimum()).length();
+        let padding = Vec3::constant_new(reach * (1. - f64::cos(max_step_angle / 2.)));
+
+        Aabb::new(swept.minimum() - padding, swept.maximum() + padding)
+    }
+}
+
+// Unit quaternion, only used to interpolate rotations
+#[derive(Clone, Copy, Debug)]
+struct Quaternion {
+    w: f64,
+    x: f64,
+    y: f64,
+    z: f64,
+}
+
+impl Quaternion {
+    const IDENTITY: Quaternion = Quaternion { w: 1., x: 0., y: 0., z: 0. };
+
+    fn from_axis_angle(axis: Vec3, degrees: f64) -> Quaternion {
+        let axis = axis.unit_vector();
+        let (sin, cos) = (degrees.to_radians() / 2.).sin_cos();
+
+        Quaternion {
+            w: cos,
+            x: axis.x() * sin,
+            y: axis.y() * sin,
+            z: axis.z() * sin,
+        }
+    }
+
+    fn dot(a: &Quaternion, b: &Quaternion) -> f64 {
+        a.w * b.w + a.x * b.x + a.y * b.y + a.z * b.z
+    }
+
+    // Rotation angle in radians taking a to b the short way
+    fn angle_between(a: &Quaternion, b: &Quaternion) -> f64 {
+        2. * Quaternion::dot(a, b).abs().min(1.).acos()
+    }
+
+    fn slerp(a: &Quaternion, b: &Quaternion, fraction: f64) -> Quaternion {
+        // q and -q are the same rotation, pick the one closer to a
+        let dot = Quaternion::dot(a, b);
+        let (b, dot) = if dot < 0. { (Quaternion { w: -b.w, x: -b.x, y: -b.y, z: -b.z }, -dot) } else { (*b, dot) };
+
+        let (weight_a, weight_b) = if dot > 0.9995 {
+            // Nearly equal, fall back to a linear blend
+            (1. - fraction, fraction)
+        } else {
+            let theta = dot.acos();
+            let sin_theta = theta.sin();
+            (((1. - fraction) * theta).sin() / sin_theta, (fraction * theta).sin() / sin_theta)
+        };
+
+        let blend = Quaternion {
+            w: weight_a * a.w + weight_b * b.w,
+            x: weight_a * a.x + weight_b * b.x,
+            y: weight_a * a.y + weight_b * b.y,
+            z: weight_a * a.z + weight_b * b.z,
+        };
+        let length = Quaternion::dot(&blend, &blend).sqrt();
+
+        Quaternion { w: blend.w / length, x: blend.x / length, y: blend.y / length, z: blend.z / length }
+    }
+
+    fn to_transform(self) -> Transform {
+        let axis = Vec3::new(self.x, self.y, self.z);
+        let sin_half = axis.length();
+        if sin_half < 1e-12 {
+            return Transform::identity();
+        }
+
+        let degrees = (2. * f64::atan2(sin_half, self.w)).to_degrees();
+        Transform::rotation(axis, degrees)
+    }
+}
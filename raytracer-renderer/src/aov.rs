@@ -0,0 +1,217 @@
+use crate::background::Background;
+use crate::camera::Camera;
+use crate::framebuffer::Framebuffer;
+use crate::ray::Ray;
+use crate::rng::splitmix64;
+use crate::tonemap::srgb_eotf;
+use crate::vec3::Vec3;
+use crate::world::World;
+
+use serde::{ Serialize, Deserialize };
+
+// Arbitrary output variables, images of what the camera rays hit first
+// besides the rendered color
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Aov {
+    // Distance along the viewing direction, 0 where nothing was hit
+    Depth,
+    // Shading normal in world space, facing the camera
+    Normal,
+    // Surface color without lighting, the background where nothing was hit
+    Albedo,
+    // Hit point in world space
+    Position,
+    // From 1 in the order objects were added to the world, 0 for the
+    // background
+    ObjectId,
+    // From 1 in the order of the material names of the scene, 0 for the
+    // background and materials without a name
+    MaterialId,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 6] = [Aov::Depth, Aov::Normal, Aov::Albedo, Aov::Position, Aov::ObjectId, Aov::MaterialId];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Position => "position",
+            Aov::ObjectId => "object_id",
+            Aov::MaterialId => "material_id",
+        }
+    }
+
+    // Channels in a multi-layer EXR, single channel AOVs keep only the
+    // first of the framebuffer
+    pub fn channels(self) -> &'static [&'static str] {
+        match self {
+            Aov::Depth => &["Z"],
+            Aov::Normal | Aov::Position => &["X", "Y", "Z"],
+            Aov::Albedo => &["R", "G", "B"],
+            Aov::ObjectId | Aov::MaterialId => &["id"],
+        }
+    }
+}
+
+// One AOV of the whole image. Single channel AOVs have the value in all three
+// channels.
+#[derive(Clone)]
+pub struct AovBuffer {
+    pub aov: Aov,
+    pub framebuffer: Framebuffer,
+}
+
+impl AovBuffer {
+    // Linear colors for looking at the AOV in an 8 bit image: depth from
+    // white at the closest hit to black at the farthest, normals and positions
+    // mapped into [0, 1] and ids as random colors. Except for the albedo they
+    // are picked as sRGB values, so they end up in the file unchanged.
+    pub fn visualize(&self) -> Framebuffer {
+        let pixels = self.framebuffer.pixels();
+        let width = self.framebuffer.width();
+        let height = self.framebuffer.height();
+
+        let colors = match self.aov {
+            Aov::Depth => {
+                let (min, max) = pixels
+                    .iter()
+                    .map(|pixel| pixel[0])
+                    .filter(|&depth| depth > 0.)
+                    .fold((f32::INFINITY, 0f32), |(min, max), depth| (min.min(depth), max.max(depth)));
+                let scale = if max > min { 1. / (max - min) } else { 0. };
+                pixels
+                    .iter()
+                    .map(|pixel| pixel.map(|depth| if depth > 0. { 1. - (depth - min) * scale } else { 0. }))
+                    .collect::<Vec<_>>()
+            }
+            Aov::Normal => pixels.iter().map(|pixel| pixel.map(|component| component * 0.5 + 0.5)).collect(),
+            Aov::Albedo => pixels.to_vec(),
+            Aov::Position => {
+                let (min, max) = pixels.iter().fold(([f32::INFINITY; 3], [f32::NEG_INFINITY; 3]), |(min, max), pixel| {
+                    ([0, 1, 2].map(|i| min[i].min(pixel[i])), [0, 1, 2].map(|i| max[i].max(pixel[i])))
+                });
+                pixels
+                    .iter()
+                    .map(|pixel| [0, 1, 2].map(|i| if max[i] > min[i] { (pixel[i] - min[i]) / (max[i] - min[i]) } else { 0. }))
+                    .collect()
+            }
+            Aov::ObjectId | Aov::MaterialId => pixels.iter().map(|pixel| id_color(pixel[0] as u64)).collect(),
+        };
+
+        let decode = |value: f32| if self.aov == Aov::Albedo { value as f64 } else { srgb_eotf(value as f64) };
+        Framebuffer::from_colors(width, height, colors.into_iter().map(|[r, g, b]| Vec3::new(decode(r), decode(g), decode(b))))
+    }
+}
+
+// Black for 0, otherwise a fixed random color
+fn id_color(id: u64) -> [f32; 3] {
+    if id == 0 {
+        return [0.; 3];
+    }
+
+    let hash = splitmix64(id);
+    [0, 1, 2].map(|channel| ((hash >> (channel * 16)) & 0xffff) as f32 / 0xffff as f32)
+}
+
+// What the camera ray of one sample hit first
+#[derive(Clone, Copy)]
+pub(crate) struct AovSample {
+    depth: f64,
+    normal: Vec3,
+    albedo: Vec3,
+    position: Vec3,
+    object_id: usize,
+    material_id: usize,
+}
+
+impl AovSample {
+    // Traces the ray once more instead of threading this through ray_color,
+    // so the samples of the color stay the same with and without AOVs
+    pub(crate) fn trace(ray: &Ray, world: &World, camera: &Camera, background: &Background) -> AovSample {
+        match world.did_hit(ray, 0.001, f64::INFINITY) {
+            Some(hit_record) => AovSample {
+                // Lens offsets are perpendicular to forward, so this is the
+                // same for every ray of the pixel
+                depth: Vec3::dot(&(hit_record.point() - ray.origin()), &camera.forward()),
+                normal: hit_record.normal(),
+                albedo: hit_record.material.albedo(&hit_record),
+                position: hit_record.point(),
+                object_id: hit_record.object_id(),
+                material_id: world.material_id(hit_record.material),
+            },
+            None => AovSample {
+                depth: 0.,
+                normal: Vec3::constant_new(0.),
+                albedo: background.color(ray),
+                position: Vec3::constant_new(0.),
+                object_id: 0,
+                material_id: 0,
+            },
+        }
+    }
+}
+
+// Sums of the AOV samples of a pixel. Ids can't be averaged, they are the
+// ones of the first sample.
+#[derive(Clone, Copy)]
+pub(crate) struct AovPixel {
+    count: usize,
+    sum: AovSample,
+}
+
+impl Default for AovPixel {
+    fn default() -> Self {
+        AovPixel {
+            count: 0,
+            sum: AovSample {
+                depth: 0.,
+                normal: Vec3::constant_new(0.),
+                albedo: Vec3::constant_new(0.),
+                position: Vec3::constant_new(0.),
+                object_id: 0,
+                material_id: 0,
+            },
+        }
+    }
+}
+
+impl AovPixel {
+    pub(crate) fn add(&mut self, sample: &AovSample) {
+        if self.count == 0 {
+            self.sum.object_id = sample.object_id;
+            self.sum.material_id = sample.material_id;
+        }
+
+        self.count += 1;
+        self.sum.depth += sample.depth;
+        self.sum.normal += sample.normal;
+        self.sum.albedo += sample.albedo;
+        self.sum.position += sample.position;
+    }
+
+    fn value(&self, aov: Aov) -> Vec3 {
+        let scale = 1. / self.count.max(1) as f64;
+
+        match aov {
+            Aov::Depth => Vec3::constant_new(self.sum.depth * scale),
+            Aov::Normal => self.sum.normal * scale,
+            Aov::Albedo => self.sum.albedo * scale,
+            Aov::Position => self.sum.position * scale,
+            Aov::ObjectId => Vec3::constant_new(self.sum.object_id as f64),
+            Aov::MaterialId => Vec3::constant_new(self.sum.material_id as f64),
+        }
+    }
+}
+
+// One buffer per AOV, the pixels may be empty if there are no AOVs
+pub(crate) fn aov_buffers(aovs: &[Aov], width: usize, height: usize, pixels: &[AovPixel]) -> Vec<AovBuffer> {
+    aovs.iter()
+        .map(|&aov| AovBuffer {
+            aov,
+            framebuffer: Framebuffer::from_colors(width, height, pixels.iter().map(|pixel| pixel.value(aov))),
+        })
+        .collect()
+}
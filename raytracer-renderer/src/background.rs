@@ -0,0 +1,129 @@
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+use image::ImageResult;
+use std::f64::consts::PI;
+use std::path::Path;
+use std::sync::Arc;
+
+// Radiance of rays that leave the scene without hitting anything
+#[derive(Clone)]
+pub enum Background {
+    Solid(Vec3),
+    // Blend from horizon to zenith by the height of the ray direction
+    Gradient { horizon: Vec3, zenith: Vec3 },
+    // Shared so that settings can be cloned without copying the image
+    Environment(Arc<EnvironmentMap>),
+}
+
+impl Background {
+    // No light from the background, for interior scenes lit by emitters only
+    pub fn black() -> Background {
+        Background::Solid(Vec3::constant_new(0.))
+    }
+
+    pub fn color(&self, ray: &Ray) -> Vec3 {
+        match self {
+            Background::Solid(color) => *color,
+            Background::Gradient { horizon, zenith } => {
+                // Linear interpolation from 0 - 1
+                let t = (ray.direction().unit_vector().y() + 1.) * 0.5;
+
+                // The more ray point upwards, the closer to the zenith color
+                *horizon * (-t + 1.0) + *zenith * t
+            }
+            Background::Environment(map) => map.color(ray.direction()),
+        }
+    }
+}
+
+// The sky of Ray Tracing in One Weekend
+impl Default for Background {
+    fn default() -> Self {
+        Background::Gradient {
+            horizon: Vec3::new(1.0, 1.0, 1.0),
+            zenith: Vec3::new(0.5, 0.7, 1.0),
+        }
+    }
+}
+
+// Equirectangular (latitude-longitude) image of the surroundings in linear
+// radiance, as stored in .hdr and .exr files. The top row is straight up and
+// the center column looks down -z.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<[f32; 3]>,
+    // Around the y axis, in radians
+    rotation: f64,
+    intensity: f64,
+}
+
+impl EnvironmentMap {
+    // Loads any format the image crate can decode, but only high dynamic
+    // range images (.hdr, .exr) make for realistic lighting
+    pub fn load<P: AsRef<Path>>(path: P) -> ImageResult<EnvironmentMap> {
+        let image = image::open(path)?.into_rgb32f();
+        let (width, height) = image.dimensions();
+
+        Ok(EnvironmentMap::new(width as usize, height as usize, image.pixels().map(|pixel| pixel.0).collect()))
+    }
+
+    pub fn new(width: usize, height: usize, pixels: Vec<[f32; 3]>) -> EnvironmentMap {
+        assert_eq!(pixels.len(), width * height, "environment map size does not match its pixels");
+
+        EnvironmentMap {
+            width,
+            height,
+            pixels,
+            rotation: 0.,
+            intensity: 1.,
+        }
+    }
+
+    // Counter-clockwise around the y axis, seen from above
+    pub fn with_rotation(mut self, degrees: f64) -> EnvironmentMap {
+        self.rotation = degrees * PI / 180.;
+        self
+    }
+
+    // Scales the radiance of every pixel
+    pub fn with_intensity(mut self, intensity: f64) -> EnvironmentMap {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn color(&self, direction: Vec3) -> Vec3 {
+        let direction = direction.unit_vector();
+
+        let phi = f64::atan2(direction.x(), -direction.z()) - self.rotation;
+        let theta = f64::acos(direction.y().clamp(-1., 1.));
+
+        let u = (phi / (2. * PI) + 0.5).rem_euclid(1.);
+        let v = theta / PI;
+
+        self.sample(u * self.width as f64, v * self.height as f64) * self.intensity
+    }
+
+    // Bilinear lookup, wrapping around horizontally and clamping at the poles
+    fn sample(&self, x: f64, y: f64) -> Vec3 {
+        let x = x - 0.5;
+        let y = (y - 0.5).clamp(0., (self.height - 1) as f64);
+
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let (tx, ty) = (x - x0, y - y0);
+
+        let column = |offset: f64| (x0 + offset).rem_euclid(self.width as f64) as usize;
+        let row = |offset: f64| usize::min((y0 + offset) as usize, self.height - 1);
+        let pixel = |column: usize, row: usize| {
+            let [r, g, b] = self.pixels[row * self.width + column];
+            Vec3::new(r as f64, g as f64, b as f64)
+        };
+
+        let top = pixel(column(0.), row(0.)) * (1. - tx) + pixel(column(1.), row(0.)) * tx;
+        let bottom = pixel(column(0.), row(1.)) * (1. - tx) + pixel(column(1.), row(1.)) * tx;
+
+        top * (1. - ty) + bottom * ty
+    }
+}
@@ -1,27 +1,650 @@
-use raytracer::camera::Camera;
-use raytracer::material::{ Lambertian, Metal, Dielectric };
+use raytracer::{ raytrace_output, RenderOutput };
+use raytracer::progressive::ProgressiveRenderer;
+use raytracer::scene::Scene;
+use raytracer::tile::TileOrder;
+use raytracer::observer::{ RenderObserver, RenderProgress };
+use raytracer::cancel::{ CancellationToken, RenderStatus };
+use raytracer::tonemap::{ ToneMapping, TonemapOperator };
+use raytracer::framebuffer::Framebuffer;
+use raytracer::output::{ ImageOutput, OutputFormat, DEFAULT_JPEG_QUALITY, save_exr_layers };
+use raytracer::aov::{ Aov, AovBuffer };
+use raytracer::denoise::Denoising;
+use raytracer::filter::{ FilterKind, PixelFilter };
+use raytracer::scene::{ random_scene_description, SceneDescription, SceneError };
 use raytracer::vec3::Vec3;
-use raytracer::{ raytrace, random_scene };
+use raytracer::sampler::SamplerKind;
+use raytracer::adaptive::{ AdaptiveSampling, sample_heatmap };
 
-fn main() -> std::io::Result<()> {
-    // width over height
-    let aspect_ratio = 3.0 / 2.0;
-    let image_width = 900;
-    let image_height = (image_width as f64 / aspect_ratio) as usize;
-    let samples_per_pixel = 10;
-    let max_depth = 10;
+use clap::{ Parser, ValueEnum };
+use image::ImageFormat;
+use std::fmt;
+use std::io::Write;
+use std::path::{ Path, PathBuf };
+use std::process::ExitCode;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::time::{ Duration, Instant };
 
-    let world = random_scene(10);
+// Exit codes, clap exits with 2 on its own for malformed arguments
+const EXIT_ARGUMENTS: u8 = 2;
+const EXIT_SCENE: u8 = 3;
+const EXIT_IO: u8 = 4;
 
-    let lookfrom = Vec3::new(13., 2., 3.);
-    let lookat = Vec3::new(0., 0., 0.);
-    let vup = Vec3::new(0., 1., 0.);
+// Least time between two preview writes of a progressive render
+const PREVIEW_INTERVAL: Duration = Duration::from_secs(1);
 
-    let dist_to_focus = 10.;
-    let aperture = 0.1;
-    let camera = Camera::new(lookfrom, lookat, vup, 20., aspect_ratio, aperture, dist_to_focus);
+/// Renders a scene file or a built-in scene to an image
+#[derive(Parser)]
+#[command(name = "raytracer", version)]
+struct Cli {
+    /// Scene file (TOML)
+    #[arg(conflicts_with = "builtin")]
+    scene: Option<PathBuf>,
 
-    raytrace("closer.png", image_width, image_height, samples_per_pixel, max_depth, &world, &camera);
+    /// Built-in scene, used when no scene file is given
+    #[arg(long, value_enum, default_value_t = BuiltinScene::Random)]
+    builtin: BuiltinScene,
+
+    /// Output image, the format is picked from the extension unless --format is given
+    #[arg(short, long, default_value = "render.png")]
+    output: PathBuf,
+
+    /// Output image format
+    #[arg(long, value_enum)]
+    format: Option<FormatOption>,
+
+    /// JPEG quality from 1 to 100
+    #[arg(long, default_value_t = DEFAULT_JPEG_QUALITY, value_parser = clap::value_parser!(u8).range(1..=100))]
+    jpeg_quality: u8,
+
+    /// Extra images to render alongside the color, written next to the output
+    /// as e.g. render.depth.png, or as layers of the output if it is EXR
+    #[arg(long = "aov", value_enum, value_delimiter = ',', value_name = "AOV")]
+    aovs: Vec<AovOption>,
+
+    /// Writes AOVs to their own files even if the output is EXR
+    #[arg(long)]
+    separate_aovs: bool,
+
+    /// Writes the resolved scene description (with all overrides) to a file
+    #[arg(long, value_name = "PATH")]
+    dump_scene: Option<PathBuf>,
+
+    /// Image width in pixels
+    #[arg(long)]
+    width: Option<usize>,
+
+    /// Image height in pixels
+    #[arg(long)]
+    height: Option<usize>,
+
+    /// Samples per pixel, the most a pixel gets with adaptive sampling
+    #[arg(long)]
+    spp: Option<usize>,
+
+    /// Turns on adaptive sampling: pixels stop once the error of their mean
+    /// is below this fraction of their brightness
+    #[arg(long)]
+    noise_threshold: Option<f64>,
+
+    /// Samples every pixel gets before adaptive sampling may stop it, at most
+    /// the samples per pixel
+    #[arg(long)]
+    min_spp: Option<usize>,
+
+    /// Writes an image of the samples taken per pixel, from blue (fewest) to red (most)
+    #[arg(long, value_name = "PATH")]
+    heatmap: Option<PathBuf>,
+
+    /// Maximum number of bounces per camera ray
+    #[arg(long)]
+    max_depth: Option<usize>,
+
+    /// Sample pattern, low discrepancy patterns converge faster
+    #[arg(long, value_enum)]
+    sampler: Option<SamplerOption>,
+
+    /// Width and height in pixels of the tiles the image is rendered in
+    #[arg(long)]
+    tile_size: Option<usize>,
+
+    /// Order the tiles are rendered in
+    #[arg(long, value_enum)]
+    tile_order: Option<TileOrderOption>,
+
+    /// Reconstruction filter the samples are weighted into the pixels around them with
+    #[arg(long, value_enum)]
+    filter: Option<FilterOption>,
+
+    /// Radius of the reconstruction filter in pixels, defaults to the usual one of the filter
+    #[arg(long, value_name = "PIXELS")]
+    filter_radius: Option<f64>,
+
+    /// Camera position as x,y,z
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    look_from: Option<Vec3>,
+
+    /// Point the camera looks at as x,y,z
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    look_at: Option<Vec3>,
+
+    /// Camera up direction as x,y,z
+    #[arg(long, value_parser = parse_vec3, allow_hyphen_values = true)]
+    vup: Option<Vec3>,
+
+    /// Vertical field of view in degrees
+    #[arg(long)]
+    vfov: Option<f64>,
+
+    /// Lens diameter, 0 for a pinhole camera
+    #[arg(long)]
+    aperture: Option<f64>,
+
+    /// Distance to the plane in focus
+    #[arg(long)]
+    focus_dist: Option<f64>,
+
+    /// Seed for sampling and for generating built-in scenes, the same seed
+    /// renders the same image
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Number of render threads, defaults to one per core
+    #[arg(short = 'j', long)]
+    threads: Option<usize>,
+
+    /// Stops the render after this many seconds and writes what it has
+    #[arg(long, value_name = "SECONDS")]
+    time_limit: Option<f64>,
+
+    /// Exposure in stops for 8 bit output, +1 doubles the brightness
+    #[arg(long, allow_hyphen_values = true)]
+    exposure: Option<f64>,
+
+    /// How 8 bit output maps brightness above 1
+    #[arg(long, value_enum)]
+    tonemap: Option<TonemapOption>,
+
+    /// Luminance that extended Reinhard tone mapping maps to white
+    #[arg(long)]
+    white_point: Option<f64>,
+
+    /// Dithers 8 bit output against banding
+    #[arg(long)]
+    dither: bool,
+
+    /// Filters the noise out of the image, guided by the albedo and normals
+    #[arg(long)]
+    denoise: bool,
+
+    /// Renders one sample per pixel at a time and keeps rewriting the output
+    /// with the image so far, so stopping early still leaves a usable image
+    #[arg(long)]
+    progressive: bool,
+
+    /// Do not print progress
+    #[arg(short, long)]
+    quiet: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum BuiltinScene {
+    /// Random spheres, the cover of Ray Tracing in One Weekend
+    Random,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FormatOption {
+    Png,
+    /// PNG with 16 bits per channel
+    Png16,
+    Jpeg,
+    /// Lossless WebP
+    Webp,
+    Bmp,
+    Tga,
+    #[value(alias = "pnm")]
+    Ppm,
+    /// OpenEXR, linear 32 bit float
+    Exr,
+    /// Radiance RGBE, linear
+    Hdr,
+    /// Portable float map, linear 32 bit float
+    Pfm,
+}
+
+impl From<FormatOption> for OutputFormat {
+    fn from(format: FormatOption) -> OutputFormat {
+        match format {
+            FormatOption::Png => OutputFormat::Png,
+            FormatOption::Png16 => OutputFormat::Png16,
+            FormatOption::Jpeg => OutputFormat::Jpeg,
+            FormatOption::Webp => OutputFormat::Webp,
+            FormatOption::Bmp => OutputFormat::Bmp,
+            FormatOption::Tga => OutputFormat::Tga,
+            FormatOption::Ppm => OutputFormat::Ppm,
+            FormatOption::Exr => OutputFormat::Exr,
+            FormatOption::Hdr => OutputFormat::Hdr,
+            FormatOption::Pfm => OutputFormat::Pfm,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum AovOption {
+    /// Distance along the viewing direction
+    Depth,
+    /// World space shading normal
+    Normal,
+    /// Surface color without lighting
+    Albedo,
+    /// World space hit point
+    Position,
+    /// Index of the object from 1
+    ObjectId,
+    /// Index of the material from 1, by name and then the .mtl materials of
+    /// meshes
+    MaterialId,
+}
+
+impl From<AovOption> for Aov {
+    fn from(aov: AovOption) -> Aov {
+        match aov {
+            AovOption::Depth => Aov::Depth,
+            AovOption::Normal => Aov::Normal,
+            AovOption::Albedo => Aov::Albedo,
+            AovOption::Position => Aov::Position,
+            AovOption::ObjectId => Aov::ObjectId,
+            AovOption::MaterialId => Aov::MaterialId,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SamplerOption {
+    /// Independent random samples
+    Independent,
+    /// Jittered grid
+    Stratified,
+    /// Randomized Halton sequence
+    Halton,
+    /// Owen-scrambled Sobol sequence
+    Sobol,
+}
+
+impl From<SamplerOption> for SamplerKind {
+    fn from(sampler: SamplerOption) -> SamplerKind {
+        match sampler {
+            SamplerOption::Independent => SamplerKind::Independent,
+            SamplerOption::Stratified => SamplerKind::Stratified,
+            SamplerOption::Halton => SamplerKind::Halton,
+            SamplerOption::Sobol => SamplerKind::Sobol,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TileOrderOption {
+    /// Rows from the top
+    Scanline,
+    /// Outwards from the center
+    Spiral,
+    /// Along a Hilbert curve
+    Hilbert,
+}
+
+impl From<TileOrderOption> for TileOrder {
+    fn from(order: TileOrderOption) -> TileOrder {
+        match order {
+            TileOrderOption::Scanline => TileOrder::Scanline,
+            TileOrderOption::Spiral => TileOrder::Spiral,
+            TileOrderOption::Hilbert => TileOrder::Hilbert,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FilterOption {
+    /// Mean of the samples within the radius, 0.5 by default
+    Box,
+    /// Linear falloff, radius 1 by default
+    Tent,
+    /// Radius 1.5 by default
+    Gaussian,
+    /// Mitchell-Netravali cubic, radius 2 by default
+    Mitchell,
+    /// Windowed sinc, radius 3 by default
+    Lanczos,
+}
+
+impl From<FilterOption> for FilterKind {
+    fn from(filter: FilterOption) -> FilterKind {
+        match filter {
+            FilterOption::Box => FilterKind::Box,
+            FilterOption::Tent => FilterKind::Tent,
+            FilterOption::Gaussian => FilterKind::Gaussian,
+            FilterOption::Mitchell => FilterKind::Mitchell,
+            FilterOption::Lanczos => FilterKind::Lanczos,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TonemapOption {
+    /// Cut off at 1
+    Clamp,
+    /// Reinhard on the luminance
+    Reinhard,
+    /// Reinhard reaching white at --white-point
+    ExtendedReinhard,
+    /// ACES filmic curve
+    Aces,
+}
+
+impl From<TonemapOption> for TonemapOperator {
+    fn from(operator: TonemapOption) -> TonemapOperator {
+        match operator {
+            TonemapOption::Clamp => TonemapOperator::Clamp,
+            TonemapOption::Reinhard => TonemapOperator::Reinhard,
+            TonemapOption::ExtendedReinhard => TonemapOperator::ExtendedReinhard,
+            TonemapOption::Aces => TonemapOperator::Aces,
+        }
+    }
+}
+
+enum CliError {
+    Arguments(String),
+    Scene(SceneError),
+    Io(String),
+}
+
+impl CliError {
+    fn exit_code(&self) -> ExitCode {
+        match self {
+            CliError::Arguments(_) => ExitCode::from(EXIT_ARGUMENTS),
+            CliError::Scene(_) => ExitCode::from(EXIT_SCENE),
+            CliError::Io(_) => ExitCode::from(EXIT_IO),
+        }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Arguments(message) => write!(f, "invalid arguments: {}", message),
+            CliError::Scene(error) => write!(f, "scene error: {}", error),
+            CliError::Io(message) => write!(f, "i/o error: {}", message),
+        }
+    }
+}
+
+fn parse_vec3(value: &str) -> Result<Vec3, String> {
+    let components = value
+        .split(',')
+        .map(|component| component.trim().parse::<f64>().map_err(|_| format!("invalid number '{}'", component)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    match components[..] {
+        [x, y, z] => Ok(Vec3::new(x, y, z)),
+        _ => Err(format!("expected x,y,z, found {} components", components.len())),
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match run(&cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            error.exit_code()
+        }
+    }
+}
+
+fn run(cli: &Cli) -> Result<(), CliError> {
+    let format = match cli.format {
+        Some(format) => OutputFormat::from(format),
+        None => OutputFormat::from_path(&cli.output)
+            .ok_or_else(|| CliError::Arguments(format!("cannot tell the image format of '{}', use --format", cli.output.display())))?,
+    };
+    let image_output = ImageOutput::new(format).with_jpeg_quality(cli.jpeg_quality);
+
+    if let Some(threads) = cli.threads {
+        if threads == 0 {
+            return Err(CliError::Arguments(String::from("--threads must be at least 1")));
+        }
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .map_err(|error| CliError::Arguments(error.to_string()))?;
+    }
+
+    let start = Instant::now();
+
+    let (mut description, base_dir) = match &cli.scene {
+        Some(path) => {
+            let description = SceneDescription::load(path).map_err(CliError::Scene)?;
+            (description, path.parent().unwrap_or_else(|| Path::new("")).to_path_buf())
+        }
+        None => match cli.builtin {
+            BuiltinScene::Random => (random_scene_description(10, cli.seed.unwrap_or_else(rand::random)), PathBuf::new()),
+        },
+    };
+
+    apply_overrides(cli, &mut description)?;
+
+    if let Some(path) = &cli.dump_scene {
+        description.save(path).map_err(|error| match error {
+            SceneError::Io { .. } => CliError::Io(error.to_string()),
+            error => CliError::Scene(error),
+        })?;
+    }
+
+    let mut scene = description.build(&base_dir).map_err(CliError::Scene)?;
+    for warning in &scene.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    scene.world.build_bvh();
+
+    let settings = &scene.settings;
+    log(cli, &format!("Loaded {} objects in {:.2?}", scene.world.len(), start.elapsed()));
+    log(cli, &format!("Rendering {}x{} at {} samples per pixel, max depth {}",
+                      settings.image_width, settings.image_height, settings.samples_per_pixel, settings.max_depth));
+
+    let pixel_count = settings.image_width * settings.image_height;
+    let console_progress = ConsoleProgress { passes: AtomicUsize::new(0) };
+    let observer: &dyn RenderObserver = if cli.quiet { &() } else { &console_progress };
+
+    let render_start = Instant::now();
+    let output = if cli.progressive {
+        render_progressive(cli, &scene, &image_output, observer)?
+    } else {
+        raytrace_output(settings, &scene.world, &scene.camera, observer, &CancellationToken::new())
+    };
+    match output.status {
+        RenderStatus::Completed => log(cli, &format!("Rendered in {:.2?}", render_start.elapsed())),
+        // Only the time limit stops renders from the command line
+        _ => log(cli, &format!("Stopped at the time limit after {:.2?}", render_start.elapsed())),
+    }
+    if settings.adaptive.is_some() {
+        let total_samples: usize = output.sample_counts.iter().sum();
+        log(cli, &format!("Took {:.1} samples per pixel on average", total_samples as f64 / pixel_count as f64));
+    }
+
+    if output.aovs.is_empty() || format != OutputFormat::Exr || cli.separate_aovs {
+        save_output(&cli.output, &image_output, &output.framebuffer, &settings.tone_mapping)?;
+        save_aovs(cli, &image_output, &output.aovs)?;
+    } else {
+        save_exr_layers(&cli.output, &output.framebuffer, &output.aovs)
+            .map_err(|error| CliError::Io(format!("{}: {}", cli.output.display(), error)))?;
+    }
+
+    if let Some(path) = &cli.heatmap {
+        let min_samples = settings.adaptive.map_or(0, |adaptive| adaptive.min_samples);
+        let heatmap = sample_heatmap(&output.sample_counts, min_samples, settings.samples_per_pixel);
+        let heatmap_format = ImageFormat::from_path(path)
+            .map_err(|_| CliError::Arguments(format!("cannot tell the image format of '{}'", path.display())))?;
+        save_image(path, &heatmap, settings.image_width, settings.image_height, heatmap_format)?;
+    }
+
+    log(cli, &format!("Wrote {} in {:.2?} total", cli.output.display(), start.elapsed()));
+
+    Ok(())
+}
+
+// Renders pass by pass, writing the image so far at most every PREVIEW_INTERVAL
+fn render_progressive(cli: &Cli, scene: &Scene, image_output: &ImageOutput, observer: &dyn RenderObserver)
+                      -> Result<RenderOutput, CliError> {
+    let settings = &scene.settings;
+    let mut renderer = ProgressiveRenderer::new(settings, &scene.world, &scene.camera).with_observer(observer);
+    let mut last_preview = Instant::now();
+
+    while renderer.render_pass() {
+        if last_preview.elapsed() >= PREVIEW_INTERVAL {
+            save_output(&cli.output, image_output, &renderer.framebuffer(), &settings.tone_mapping)?;
+            last_preview = Instant::now();
+        }
+    }
+
+    Ok(renderer.output())
+}
+
+// Keeps one line of stderr updated with how far the render is
+struct ConsoleProgress {
+    // Finished passes of a progressive render
+    passes: AtomicUsize,
+}
+
+impl ConsoleProgress {
+    fn print(&self, progress: &RenderProgress) {
+        let passes = self.passes.load(Ordering::Relaxed);
+        let passes = if passes > 0 { format!(", pass {}", passes) } else { String::new() };
+        let eta = progress.eta().map_or(String::new(), |eta| format!(", {}s left", eta.as_secs()));
+
+        // Trailing spaces clear what is left of a longer previous line
+        eprint!("\r{:3.0}%{}{}    ", progress.fraction() * 100., passes, eta);
+        let _ = std::io::stderr().flush();
+    }
+}
+
+impl RenderObserver for ConsoleProgress {
+    fn pass_finished(&self, pass: usize, progress: &RenderProgress) {
+        self.passes.store(pass, Ordering::Relaxed);
+        self.print(progress);
+    }
+
+    fn progress(&self, progress: &RenderProgress) {
+        self.print(progress);
+    }
+
+    fn completed(&self, progress: &RenderProgress) {
+        self.print(progress);
+        eprintln!();
+    }
+
+    fn stopped(&self, _status: RenderStatus, progress: &RenderProgress) {
+        self.print(progress);
+        eprintln!();
+    }
+}
+
+fn save_output(path: &Path, image_output: &ImageOutput, framebuffer: &Framebuffer, tone_mapping: &ToneMapping)
+               -> Result<(), CliError> {
+    image_output
+        .save(path, framebuffer, tone_mapping)
+        .map_err(|error| CliError::Io(format!("{}: {}", path.display(), error)))
+}
+
+// Next to the output as <stem>.<aov>.<extension>. Float formats get the
+// values, the others a picture of them.
+fn save_aovs(cli: &Cli, image_output: &ImageOutput, aovs: &[AovBuffer]) -> Result<(), CliError> {
+    for buffer in aovs {
+        let mut file_name = cli.output.file_stem().unwrap_or_default().to_os_string();
+        file_name.push(format!(".{}", buffer.aov.name()));
+        if let Some(extension) = cli.output.extension() {
+            file_name.push(".");
+            file_name.push(extension);
+        }
+        let path = cli.output.with_file_name(file_name);
+
+        let framebuffer = if image_output.format.is_linear() { buffer.framebuffer.clone() } else { buffer.visualize() };
+        save_output(&path, image_output, &framebuffer, &ToneMapping::default())?;
+        log(cli, &format!("Wrote {}", path.display()));
+    }
+
+    Ok(())
+}
+
+fn save_image(path: &Path, buffer: &[u8], width: usize, height: usize, format: ImageFormat) -> Result<(), CliError> {
+    image::save_buffer_with_format(path, buffer, width as u32, height as u32, image::ColorType::Rgb8, format)
+        .map_err(|error| CliError::Io(format!("{}: {}", path.display(), error)))
+}
+
+fn apply_overrides(cli: &Cli, description: &mut SceneDescription) -> Result<(), CliError> {
+    let render = &mut description.render;
+    render.width = cli.width.unwrap_or(render.width);
+    render.height = cli.height.unwrap_or(render.height);
+    render.samples_per_pixel = cli.spp.unwrap_or(render.samples_per_pixel);
+    render.max_depth = cli.max_depth.unwrap_or(render.max_depth);
+    render.seed = cli.seed.unwrap_or(render.seed);
+    render.sampler = cli.sampler.map_or(render.sampler, SamplerKind::from);
+    render.time_limit = cli.time_limit.or(render.time_limit);
+    render.tile_size = cli.tile_size.unwrap_or(render.tile_size);
+    // The radius of the scene was meant for its own filter
+    if let Some(filter) = cli.filter {
+        render.filter = PixelFilter::new(FilterKind::from(filter));
+    }
+    render.filter.radius = cli.filter_radius.or(render.filter.radius);
+
+    // NaN and infinity would turn every pixel black or white
+    if let Some(exposure) = cli.exposure.filter(|exposure| !exposure.is_finite()) {
+        return Err(CliError::Arguments(format!("--exposure must be a finite number of stops, not {}", exposure)));
+    }
+    if let Some(white_point) = cli.white_point.filter(|white_point| !(*white_point > 0. && white_point.is_finite())) {
+        return Err(CliError::Arguments(format!("--white-point must be greater than 0, not {}", white_point)));
+    }
+    let tone_mapping = &mut render.tone_mapping;
+    tone_mapping.exposure = cli.exposure.unwrap_or(tone_mapping.exposure);
+    tone_mapping.operator = cli.tonemap.map_or(tone_mapping.operator, TonemapOperator::from);
+    tone_mapping.white_point = cli.white_point.unwrap_or(tone_mapping.white_point);
+    tone_mapping.dither |= cli.dither;
+    render.tile_order = cli.tile_order.map_or(render.tile_order, TileOrder::from);
+    for &aov in &cli.aovs {
+        if !render.aovs.contains(&Aov::from(aov)) {
+            render.aovs.push(Aov::from(aov));
+        }
+    }
+    if cli.denoise {
+        render.denoise.get_or_insert_with(Denoising::default);
+    }
+    if let Some(noise_threshold) = cli.noise_threshold {
+        render.adaptive.get_or_insert_with(AdaptiveSampling::default).noise_threshold = noise_threshold;
+    }
+    if let Some(min_spp) = cli.min_spp {
+        let Some(adaptive) = &mut render.adaptive else {
+            return Err(CliError::Arguments(String::from("--min-spp needs adaptive sampling, set --noise-threshold")));
+        };
+        if min_spp > render.samples_per_pixel {
+            return Err(CliError::Arguments(format!("--min-spp must be at most the {} samples per pixel", render.samples_per_pixel)));
+        }
+        adaptive.min_samples = min_spp;
+    }
+
+    let camera = &mut description.camera;
+    camera.look_from = cli.look_from.unwrap_or(camera.look_from);
+    camera.look_at = cli.look_at.unwrap_or(camera.look_at);
+    camera.vup = cli.vup.unwrap_or(camera.vup);
+    camera.vfov = cli.vfov.unwrap_or(camera.vfov);
+    camera.aperture = cli.aperture.unwrap_or(camera.aperture);
+    if cli.focus_dist.is_some() {
+        camera.focus_dist = cli.focus_dist;
+    }
 
     Ok(())
 }
+
+fn log(cli: &Cli, message: &str) {
+    if !cli.quiet {
+        eprintln!("{}", message);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::aabb::Aabb;
+use crate::hittable::HitRecord;
+use crate::ray::Ray;
+use crate::vec3::Vec3;
+
+// Number of buckets the centroid range is split into when evaluating the SAH
+const BIN_COUNT: usize = 12;
+const MAX_PRIMITIVES_IN_LEAF: usize = 4;
+// Deeper subtrees are collapsed into a leaf, which bounds the traversal stack
+const MAX_DEPTH: usize = 64;
+
+// Cost of visiting a node relative to intersecting one primitive
+const TRAVERSAL_COST: f64 = 0.125;
+const INTERSECTION_COST: f64 = 1.;
+
+#[derive(Clone, Copy)]
+enum NodeKind {
+    // Range into primitive_indices
+    Leaf { first: usize, count: usize },
+    // The left child is always stored right after its parent
+    Interior { right: usize, axis: usize },
+}
+
+struct BvhNode {
+    bounds: Aabb,
+    kind: NodeKind,
+}
+
+// Bounding volume hierarchy built with the surface area heuristic and stored
+// as a flat array of nodes. It only knows about the bounding boxes of the
+// primitives, the owner performs the actual intersection through a callback.
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    primitive_indices: Vec<usize>,
+}
+
+impl Bvh {
+    pub fn build(boxes: &[Aabb]) -> Bvh {
+        let mut primitive_indices: Vec<usize> = (0..boxes.len()).collect();
+        let centroids: Vec<Vec3> = boxes.iter().map(|b| b.centroid()).collect();
+        let mut nodes = Vec::with_capacity(2 * boxes.len());
+
+        if !boxes.is_empty() {
+            Bvh::build_recursive(boxes, &centroids, &mut primitive_indices, 0, boxes.len(), 0, &mut nodes);
+        }
+
+        Bvh {
+            nodes,
+            primitive_indices,
+        }
+    }
+
+    pub fn bounding_box(&self) -> Option<Aabb> {
+        self.nodes.first().map(|root| root.bounds)
+    }
+
+    fn build_recursive(boxes: &[Aabb], centroids: &[Vec3], indices: &mut [usize],
+                       start: usize, end: usize, depth: usize, nodes: &mut Vec<BvhNode>) -> usize {
+        let count = end - start;
+        let bounds = indices[start..end]
+            .iter()
+            .fold(Aabb::empty(), |acc, &i| Aabb::surrounding_box(&acc, &boxes[i]));
+
+        let node_index = nodes.len();
+        nodes.push(BvhNode { bounds, kind: NodeKind::Leaf { first: start, count } });
+
+        if count == 1 || depth >= MAX_DEPTH {
+            return node_index;
+        }
+
+        let centroid_bounds = indices[start..end]
+            .iter()
+            .fold(Aabb::empty(), |acc, &i| Aabb::surrounding_box(&acc, &Aabb::new(centroids[i], centroids[i])));
+
+        let axis = centroid_bounds.longest_axis();
+        let axis_min = centroid_bounds.minimum()[axis];
+        let axis_extent = centroid_bounds.maximum()[axis] - axis_min;
+
+        // Every centroid is at the same spot, no split can separate them
+        if axis_extent <= 0. {
+            return node_index;
+        }
+
+        let bin_of = |i: usize| -> usize {
+            let bin = ((centroids[i][axis] - axis_min) / axis_extent * BIN_COUNT as f64) as usize;
+            usize::min(bin, BIN_COUNT - 1)
+        };
+
+        let mut bin_bounds = [Aabb::empty(); BIN_COUNT];
+        let mut bin_counts = [0usize; BIN_COUNT];
+        for &i in &indices[start..end] {
+            let bin = bin_of(i);
+            bin_bounds[bin] = Aabb::surrounding_box(&bin_bounds[bin], &boxes[i]);
+            bin_counts[bin] += 1;
+        }
+
+        // Sweep from both ends so every split plane is evaluated in linear time
+        let mut left_cost = [0.; BIN_COUNT - 1];
+        let mut left_box = Aabb::empty();
+        let mut left_count = 0;
+        for split in 0..BIN_COUNT - 1 {
+            left_box = Aabb::surrounding_box(&left_box, &bin_bounds[split]);
+            left_count += bin_counts[split];
+            left_cost[split] = left_box.surface_area() * left_count as f64;
+        }
+
+        let mut right_box = Aabb::empty();
+        let mut right_count = 0;
+        let mut best_split = 0;
+        let mut best_cost = f64::INFINITY;
+        for split in (0..BIN_COUNT - 1).rev() {
+            right_box = Aabb::surrounding_box(&right_box, &bin_bounds[split + 1]);
+            right_count += bin_counts[split + 1];
+            let cost = left_cost[split] + right_box.surface_area() * right_count as f64;
+            if cost < best_cost {
+                best_cost = cost;
+                best_split = split;
+            }
+        }
+
+        let parent_area = bounds.surface_area();
+        let split_cost = if parent_area > 0. {
+            TRAVERSAL_COST + INTERSECTION_COST * best_cost / parent_area
+        } else {
+            TRAVERSAL_COST
+        };
+        let leaf_cost = INTERSECTION_COST * count as f64;
+
+        if count <= MAX_PRIMITIVES_IN_LEAF && split_cost >= leaf_cost {
+            return node_index;
+        }
+
+        // Partition the range so primitives left of the split come first
+        let mut mid = start;
+        for i in start..end {
+            if bin_of(indices[i]) <= best_split {
+                indices.swap(i, mid);
+                mid += 1;
+            }
+        }
+
+        if mid == start || mid == end {
+            mid = start + count / 2;
+        }
+
+        Bvh::build_recursive(boxes, centroids, indices, start, mid, depth + 1, nodes);
+        let right = Bvh::build_recursive(boxes, centroids, indices, mid, end, depth + 1, nodes);
+        nodes[node_index].kind = NodeKind::Interior { right, axis };
+
+        node_index
+    }
+
+    // Finds the closest hit, calling hit_primitive with the index of a
+    // primitive and the closest t found so far for every candidate
+    pub fn hit<'a, F>(&self, ray: &Ray, t_min: f64, t_max: f64, mut hit_primitive: F) -> Option<HitRecord<'a>>
+    where
+        F: FnMut(usize, f64) -> Option<HitRecord<'a>>,
+    {
+        if self.nodes.is_empty() {
+            return None;
+        }
+
+        let origin = ray.origin();
+        let direction = ray.direction();
+        let inv_direction = Vec3::new(1. / direction.x(), 1. / direction.y(), 1. / direction.z());
+
+        let mut track_hit_record = None;
+        let mut closest_so_far = t_max;
+
+        let mut stack = [0usize; MAX_DEPTH + 1];
+        let mut stack_size = 0;
+        let mut current = 0;
+
+        loop {
+            let node = &self.nodes[current];
+
+            if node.bounds.intersects(origin, inv_direction, t_min, closest_so_far) {
+                match node.kind {
+                    NodeKind::Leaf { first, count } => {
+                        for &primitive in &self.primitive_indices[first..first + count] {
+                            if let Some(hit_record) = hit_primitive(primitive, closest_so_far) {
+                                closest_so_far = hit_record.t();
+                                track_hit_record = Some(hit_record);
+                            }
+                        }
+                    }
+                    NodeKind::Interior { right, axis } => {
+                        // Visit the child closer to the ray origin first
+                        let (near, far) = if inv_direction[axis] < 0. {
+                            (right, current + 1)
+                        } else {
+                            (current + 1, right)
+                        };
+
+                        stack[stack_size] = far;
+                        stack_size += 1;
+                        current = near;
+                        continue;
+                    }
+                }
+            }
+
+            if stack_size == 0 {
+                break;
+            }
+            stack_size -= 1;
+            current = stack[stack_size];
+        }
+
+        track_hit_record
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::material::{ Lambertian, Material };
+    use crate::ray::Ray;
+    use crate::rng::Pcg32;
+    use crate::sphere::Sphere;
+    use crate::triangle::Triangle;
+    use crate::vec3::Vec3;
+    use crate::world::World;
+
+    use std::sync::Arc;
+
+    fn random_point(rng: &mut Pcg32, scale: f64) -> Vec3 {
+        Vec3::new(rng.next_f64() - 0.5, rng.next_f64() - 0.5, rng.next_f64() - 0.5) * scale
+    }
+
+    // Spheres and triangles of different sizes, so the SAH splits unevenly
+    fn random_world(rng: &mut Pcg32, count: usize) -> World<'static> {
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::constant_new(0.5)));
+        let mut world = World::new();
+
+        for i in 0..count {
+            let center = random_point(rng, 20.);
+            if i % 2 == 0 {
+                world.add(Box::new(Sphere::new(center, 0.05 + rng.next_f64(), material.clone())));
+            } else {
+                let vertices = [0, 1, 2].map(|_| center + random_point(rng, 2.));
+                world.add(Box::new(Triangle::new(vertices, material.clone())));
+            }
+        }
+
+        world
+    }
+
+    #[test]
+    fn bvh_hits_match_brute_force() {
+        let mut rng = Pcg32::new(7, 0);
+        let linear = random_world(&mut Pcg32::new(1, 0), 500);
+        let mut bvh = random_world(&mut Pcg32::new(1, 0), 500);
+        bvh.build_bvh();
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let ray = Ray::new(random_point(&mut rng, 30.), random_point(&mut rng, 1.));
+            let expected = linear.did_hit(&ray, 0.001, f64::INFINITY);
+            let actual = bvh.did_hit(&ray, 0.001, f64::INFINITY);
+
+            match (expected, actual) {
+                (Some(expected), Some(actual)) => {
+                    assert_eq!(expected.t(), actual.t());
+                    assert_eq!(expected.object_id(), actual.object_id());
+                    hits += 1;
+                }
+                (None, None) => {}
+                (expected, actual) => panic!("linear hit {} but the BVH hit {}", expected.is_some(), actual.is_some()),
+            }
+        }
+
+        // Both outcomes have to be covered for the comparison to mean anything
+        assert!(hits > 100 && hits < 1900, "{} of 2000 rays hit", hits);
+    }
+
+    #[test]
+    fn empty_world_has_no_hits() {
+        let mut world = World::new();
+        world.build_bvh();
+
+        assert!(world.did_hit(&Ray::new(Vec3::constant_new(0.), Vec3::new(0., 0., -1.)), 0.001, f64::INFINITY).is_none());
+    }
+}
@@ -1,5 +1,6 @@
 use crate::ray::Ray;
 use crate::vec3::Vec3;
+use crate::sampler::Sampler;
 
 pub struct Camera {
     origin: Vec3,
@@ -8,8 +9,12 @@ pub struct Camera {
     lower_left_corner: Vec3,
     u: Vec3,
     v: Vec3,
+    // Points away from where the camera looks
     w: Vec3,
     lens_radius: f64,
+    // Rays get a random time in [shutter_open, shutter_close]
+    shutter_open: f64,
+    shutter_close: f64,
 }
 
 impl Camera {
@@ -43,17 +48,40 @@ impl Camera {
             v,
             w,
             lens_radius,
+            shutter_open: 0.,
+            shutter_close: 0.,
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = Vec3::random_in_unit_disk() * self.lens_radius;
+    // Keeps the shutter open over a time span, objects moving within it get
+    // motion blur
+    pub fn with_shutter(mut self, open: f64, close: f64) -> Camera {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self
+    }
+
+    // Unit vector the camera looks along
+    pub fn forward(&self) -> Vec3 {
+        -self.w
+    }
+
+    pub fn get_ray(&self, s: f64, t: f64, sampler: &mut dyn Sampler) -> Ray {
+        let rd = Vec3::random_in_unit_disk(sampler) * self.lens_radius;
         let offset = self.u * rd.x() + self.v * rd.y();
 
         Ray::new(
             self.origin + offset, 
             self.lower_left_corner + self.horizontal * s + self.vertical * t - self.origin - offset,
-        )
+        ).with_time(self.shutter_time(sampler))
+    }
+
+    fn shutter_time(&self, sampler: &mut dyn Sampler) -> f64 {
+        if self.shutter_close > self.shutter_open {
+            self.shutter_open + (self.shutter_close - self.shutter_open) * sampler.get_1d()
+        } else {
+            self.shutter_open
+        }
     }
 }
 
@@ -0,0 +1,114 @@
+use std::sync::Arc;
+use std::sync::atomic::{ AtomicBool, AtomicU8, Ordering };
+use std::time::{ Duration, Instant };
+
+// Stops a running render from another thread. Clones share the flag, so one
+// can go to the render and the other stay with whoever decides to stop it.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+// How a render ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RenderStatus {
+    // Every pixel got all its samples
+    Completed,
+    // Stopped through the cancellation token
+    Cancelled,
+    // Ran out of RenderSettings::time_limit
+    TimedOut,
+}
+
+// Checked by the render threads between samples, so a render stops within
+// about one sample of being cancelled or running out of time
+pub(crate) struct StopCheck {
+    token: CancellationToken,
+    // None for time limits too far out to ever be reached
+    deadline: Option<Instant>,
+    // Why the render stopped, the first reason found sticks
+    stopped: AtomicU8,
+}
+
+const RUNNING: u8 = 0;
+const CANCELLED: u8 = 1;
+const TIMED_OUT: u8 = 2;
+
+impl StopCheck {
+    pub(crate) fn new(token: CancellationToken, time_limit: Option<Duration>) -> StopCheck {
+        StopCheck {
+            token,
+            deadline: time_limit.and_then(|time_limit| Instant::now().checked_add(time_limit)),
+            stopped: AtomicU8::new(RUNNING),
+        }
+    }
+
+    pub(crate) fn should_stop(&self) -> bool {
+        if self.stopped.load(Ordering::Relaxed) != RUNNING {
+            return true;
+        }
+
+        let reason = if self.token.is_cancelled() {
+            CANCELLED
+        } else if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            TIMED_OUT
+        } else {
+            return false;
+        };
+        let _ = self.stopped.compare_exchange(RUNNING, reason, Ordering::Relaxed, Ordering::Relaxed);
+        true
+    }
+
+    // Completed unless should_stop ever said so
+    pub(crate) fn status(&self) -> RenderStatus {
+        match self.stopped.load(Ordering::Relaxed) {
+            RUNNING => RenderStatus::Completed,
+            CANCELLED => RenderStatus::Cancelled,
+            _ => RenderStatus::TimedOut,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_limits_past_the_end_of_time_never_stop() {
+        let stop = StopCheck::new(CancellationToken::new(), Some(Duration::from_secs_f64(1e19)));
+
+        assert!(!stop.should_stop());
+        assert_eq!(stop.status(), RenderStatus::Completed);
+    }
+
+    #[test]
+    fn the_first_reason_to_stop_sticks() {
+        let token = CancellationToken::new();
+        let stop = StopCheck::new(token.clone(), Some(Duration::ZERO));
+
+        assert!(stop.should_stop());
+        token.cancel();
+        assert!(stop.should_stop());
+        assert_eq!(stop.status(), RenderStatus::TimedOut);
+
+        let token = CancellationToken::new();
+        let stop = StopCheck::new(token.clone(), None);
+        token.cancel();
+        assert!(stop.should_stop());
+        assert_eq!(stop.status(), RenderStatus::Cancelled);
+    }
+}
@@ -0,0 +1,236 @@
+use crate::framebuffer::Framebuffer;
+use crate::vec3::Vec3;
+
+use rayon::prelude::*;
+use serde::{ Serialize, Deserialize };
+
+// B3 spline, the filter of every pass is this in both directions
+const KERNEL: [f32; 5] = [1. / 16., 1. / 4., 3. / 8., 1. / 4., 1. / 16.];
+// Darker albedo is not divided out, it would blow the noise up
+const MIN_ALBEDO: f32 = 0.01;
+
+// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010) that blurs the
+// noise but not across edges in the color, normal or albedo
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Denoising {
+    // Each pass reaches twice as far as the one before, 5 cover 125 pixels
+    pub passes: usize,
+    // Lighting difference up to which neighbours count, halved every pass
+    pub color_sigma: f64,
+    // Higher keeps edges between surfaces facing different ways sharper
+    pub normal_power: f64,
+    pub albedo_sigma: f64,
+}
+
+impl Default for Denoising {
+    fn default() -> Self {
+        Denoising {
+            passes: 5,
+            color_sigma: 4.,
+            normal_power: 64.,
+            albedo_sigma: 0.1,
+        }
+    }
+}
+
+impl Denoising {
+    // The guides are the albedo and normal AOVs of the same render
+    pub fn denoise(&self, color: &Framebuffer, albedo: &Framebuffer, normal: &Framebuffer) -> Framebuffer {
+        let (width, height) = (color.width(), color.height());
+
+        // Filters the lighting without the texture, which has no noise of its
+        // own to remove
+        let albedo = albedo.pixels().iter().map(|pixel| pixel.map(|channel| channel.max(MIN_ALBEDO))).collect::<Vec<_>>();
+        let mut lighting = color
+            .pixels()
+            .iter()
+            .zip(&albedo)
+            .map(|(color, albedo)| [0, 1, 2].map(|i| color[i] / albedo[i]))
+            .collect::<Vec<_>>();
+        let normal = normal.pixels().iter().map(|&pixel| normalize(pixel)).collect::<Vec<_>>();
+
+        for pass in 0..self.passes {
+            // Steps beyond the image leave every pixel as it is
+            let step = match u32::try_from(pass).ok().and_then(|pass| 1usize.checked_shl(pass)) {
+                Some(step) if step <= width.max(height) => step,
+                _ => break,
+            };
+            let guides = Guides {
+                width,
+                height,
+                albedo: &albedo,
+                normal: &normal,
+                step,
+                color_sigma: self.color_sigma as f32 / step as f32,
+                normal_power: self.normal_power as f32,
+                albedo_sigma: self.albedo_sigma as f32,
+            };
+
+            lighting = (0..width * height)
+                .into_par_iter()
+                .map(|i| guides.filter(&lighting, i))
+                .collect();
+        }
+
+        let colors = lighting
+            .iter()
+            .zip(&albedo)
+            .map(|(lighting, albedo)| Vec3::new((lighting[0] * albedo[0]) as f64, (lighting[1] * albedo[1]) as f64,
+                                                (lighting[2] * albedo[2]) as f64));
+        Framebuffer::from_colors(width, height, colors)
+    }
+}
+
+// Everything one pass needs besides the lighting it filters
+struct Guides<'a> {
+    width: usize,
+    height: usize,
+    albedo: &'a [[f32; 3]],
+    normal: &'a [[f32; 3]],
+    // Pixels between the taps of the kernel
+    step: usize,
+    color_sigma: f32,
+    normal_power: f32,
+    albedo_sigma: f32,
+}
+
+impl Guides<'_> {
+    // Weighted mean of the 5x5 taps around pixel i
+    fn filter(&self, lighting: &[[f32; 3]], i: usize) -> [f32; 3] {
+        let (x, y) = ((i % self.width) as isize, (i / self.width) as isize);
+        let mut sum = [0.; 3];
+        let mut weight_sum = 0.;
+
+        for (ky, &kernel_y) in KERNEL.iter().enumerate() {
+            let qy = y + (ky as isize - 2) * self.step as isize;
+            if qy < 0 || qy >= self.height as isize {
+                continue;
+            }
+
+            for (kx, &kernel_x) in KERNEL.iter().enumerate() {
+                let qx = x + (kx as isize - 2) * self.step as isize;
+                if qx < 0 || qx >= self.width as isize {
+                    continue;
+                }
+
+                let q = qy as usize * self.width + qx as usize;
+                let weight = kernel_x * kernel_y
+                    * f32::exp(-distance_squared(lighting[i], lighting[q]) / (self.color_sigma * self.color_sigma))
+                    * f32::exp(-distance_squared(self.albedo[i], self.albedo[q]) / (self.albedo_sigma * self.albedo_sigma))
+                    * self.normal_weight(i, q);
+
+                for channel in 0..3 {
+                    sum[channel] += lighting[q][channel] * weight;
+                }
+                weight_sum += weight;
+            }
+        }
+
+        // The center tap always counts fully, so this is never 0
+        sum.map(|channel| channel / weight_sum)
+    }
+
+    fn normal_weight(&self, p: usize, q: usize) -> f32 {
+        let (normal_p, normal_q) = (self.normal[p], self.normal[q]);
+        // The background has no normal
+        if normal_p == [0.; 3] && normal_q == [0.; 3] {
+            return 1.;
+        }
+
+        let cosine = normal_p[0] * normal_q[0] + normal_p[1] * normal_q[1] + normal_p[2] * normal_q[2];
+        cosine.max(0.).powf(self.normal_power)
+    }
+}
+
+fn distance_squared(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum()
+}
+
+// Averaged normals are shorter at edges, zero stays zero
+fn normalize(vector: [f32; 3]) -> [f32; 3] {
+    let length = distance_squared(vector, [0.; 3]).sqrt();
+    if length > 0. { vector.map(|component| component / length) } else { vector }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rng::splitmix64;
+
+    const WIDTH: usize = 32;
+    const HEIGHT: usize = 32;
+
+    fn framebuffer(color: impl Fn(usize, usize) -> Vec3) -> Framebuffer {
+        Framebuffer::from_colors(WIDTH, HEIGHT, (0..WIDTH * HEIGHT).map(|i| color(i % WIDTH, i / WIDTH)))
+    }
+
+    // Gray lighting with noise of up to ±0.2, the same every run
+    fn noise(x: usize, y: usize) -> f64 {
+        let bits = splitmix64((y * WIDTH + x) as u64);
+        ((bits >> 11) as f64 / (1u64 << 53) as f64 - 0.5) * 0.4
+    }
+
+    fn variance(pixels: &[[f32; 3]]) -> f64 {
+        let values = pixels.iter().map(|pixel| pixel[0] as f64).collect::<Vec<_>>();
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        values.iter().map(|value| (value - mean) * (value - mean)).sum::<f64>() / values.len() as f64
+    }
+
+    #[test]
+    fn noise_on_flat_surfaces_is_smoothed() {
+        let color = framebuffer(|x, y| Vec3::new(0.5 + noise(x, y), 0.5 + noise(x, y), 0.5 + noise(x, y)));
+        let albedo = framebuffer(|_, _| Vec3::new(0.5, 0.5, 0.5));
+        let normal = framebuffer(|_, _| Vec3::new(0., 0., 1.));
+
+        let denoised = Denoising::default().denoise(&color, &albedo, &normal);
+
+        assert!(variance(denoised.pixels()) < variance(color.pixels()) / 10.,
+                "{} is not much below {}", variance(denoised.pixels()), variance(color.pixels()));
+    }
+
+    #[test]
+    fn edges_in_the_guides_survive() {
+        // Left half dark and facing the camera, right half bright and facing
+        // sideways, both noisy
+        let left = |x: usize| x < WIDTH / 2;
+        let albedo = framebuffer(|x, _| if left(x) { Vec3::new(0.1, 0.1, 0.1) } else { Vec3::new(0.9, 0.9, 0.9) });
+        let normal = framebuffer(|x, _| if left(x) { Vec3::new(0., 0., 1.) } else { Vec3::new(1., 0., 0.) });
+        let albedo_edge = framebuffer(|x, y| {
+            let value = if left(x) { 0.1 } else { 0.9 } * (1. + noise(x, y));
+            Vec3::new(value, value, value)
+        });
+        let flat_normal = framebuffer(|_, _| Vec3::new(0., 0., 1.));
+        let flat_albedo = framebuffer(|_, _| Vec3::new(0.5, 0.5, 0.5));
+        let normal_edge = framebuffer(|x, y| {
+            let value = if left(x) { 0.1 } else { 0.9 } + noise(x, y) * 0.25;
+            Vec3::new(value, value, value)
+        });
+
+        for (color, albedo, normal) in [(&albedo_edge, &albedo, &flat_normal), (&normal_edge, &flat_albedo, &normal)] {
+            let denoised = Denoising::default().denoise(color, albedo, normal);
+
+            // The pixels right next to the edge keep their side's brightness
+            for y in 0..HEIGHT {
+                let dark = denoised.pixels()[y * WIDTH + WIDTH / 2 - 1][0];
+                let bright = denoised.pixels()[y * WIDTH + WIDTH / 2][0];
+                assert!(dark < 0.15 && bright > 0.75, "{} and {} at row {}", dark, bright, y);
+            }
+        }
+    }
+
+    #[test]
+    fn passes_beyond_the_image_change_nothing() {
+        let color = framebuffer(|x, y| Vec3::new(0.5 + noise(x, y), 0.5, 0.5));
+        let albedo = framebuffer(|_, _| Vec3::new(0.5, 0.5, 0.5));
+        let normal = framebuffer(|_, _| Vec3::new(0., 0., 1.));
+
+        // Steps up to 32 fit the image
+        let fitting = Denoising { passes: 6, ..Denoising::default() }.denoise(&color, &albedo, &normal);
+        for passes in [7, 64, 1000] {
+            let denoised = Denoising { passes, ..Denoising::default() }.denoise(&color, &albedo, &normal);
+            assert_eq!(denoised.pixels(), fitting.pixels());
+        }
+    }
+}
@@ -0,0 +1,288 @@
+use crate::framebuffer::Framebuffer;
+use crate::vec3::Vec3;
+
+use serde::{ Serialize, Deserialize };
+
+use std::f64::consts::PI;
+use std::ops::Range;
+use std::sync::atomic::{ AtomicU64, Ordering };
+
+// Film sums are kept in 128 bit fixed point with this as 1
+const FIXED_POINT_ONE: f64 = (1u64 << 32) as f64;
+// Largest splat in fixed point, about 1.8e19 in color. A sum only overflows
+// after 2^31 splats this bright.
+const MAX_SPLAT: f64 = (1u128 << 96) as f64;
+
+// B and C of the Mitchell-Netravali filter, the ones the paper recommends
+const MITCHELL_B: f64 = 1. / 3.;
+const MITCHELL_C: f64 = 1. / 3.;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterKind {
+    // Every sample within the radius counts the same
+    #[default]
+    Box,
+    // Falls off linearly to the radius
+    Tent,
+    // Standard deviation of a third of the radius, shifted to reach 0 there
+    Gaussian,
+    // Cubic with B = C = 1/3, sharper than the Gaussian with a little ringing
+    Mitchell,
+    // Sinc windowed by a sinc as wide as the radius, the sharpest but rings
+    // the most at hard edges
+    Lanczos,
+}
+
+impl FilterKind {
+    pub fn default_radius(self) -> f64 {
+        match self {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.,
+            FilterKind::Lanczos => 3.,
+        }
+    }
+}
+
+// How a pixel is reconstructed from the samples around it. Each sample counts
+// for every pixel whose center is within the radius, weighted by the filter
+// in x times the filter in y.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PixelFilter {
+    pub kind: FilterKind,
+    // In pixels, the default radius of the kind if not given
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub radius: Option<f64>,
+}
+
+impl PixelFilter {
+    pub fn new(kind: FilterKind) -> PixelFilter {
+        PixelFilter { kind, radius: None }
+    }
+
+    pub fn with_radius(mut self, radius: f64) -> PixelFilter {
+        self.radius = Some(radius);
+        self
+    }
+
+    pub fn radius(&self) -> f64 {
+        self.radius.unwrap_or(self.kind.default_radius())
+    }
+
+    // A box of radius 0.5 only covers the pixel of the sample, which makes
+    // every pixel the plain mean of its own samples
+    pub fn is_pixel_mean(&self) -> bool {
+        self.kind == FilterKind::Box && self.radius() == 0.5
+    }
+
+    // Weight of a sample at distance x from the pixel center along one axis,
+    // for |x| up to the radius
+    pub fn weight(&self, x: f64) -> f64 {
+        let radius = self.radius();
+        let x = x.abs();
+
+        match self.kind {
+            FilterKind::Box => 1.,
+            FilterKind::Tent => f64::max(1. - x / radius, 0.),
+            FilterKind::Gaussian => {
+                let sigma = radius / 3.;
+                f64::max(gaussian(x, sigma) - gaussian(radius, sigma), 0.)
+            }
+            FilterKind::Mitchell => mitchell(2. * x / radius),
+            FilterKind::Lanczos => sinc(x) * sinc(x / radius),
+        }
+    }
+}
+
+fn gaussian(x: f64, sigma: f64) -> f64 {
+    f64::exp(-x * x / (2. * sigma * sigma))
+}
+
+// For x in [0, 2]
+fn mitchell(x: f64) -> f64 {
+    let (b, c) = (MITCHELL_B, MITCHELL_C);
+    let polynomial = if x < 1. {
+        (12. - 9. * b - 6. * c) * x * x * x + (-18. + 12. * b + 6. * c) * x * x + (6. - 2. * b)
+    } else if x < 2. {
+        (-b - 6. * c) * x * x * x + (6. * b + 30. * c) * x * x + (-12. * b - 48. * c) * x + (8. * b + 24. * c)
+    } else {
+        0.
+    };
+    polynomial / 6.
+}
+
+fn sinc(x: f64) -> f64 {
+    if x < 1e-5 {
+        return 1.;
+    }
+    f64::sin(PI * x) / (PI * x)
+}
+
+// Filter weighted sums of the samples around every pixel, which samples are
+// splatted into from any number of threads. The sums are integers, so they
+// come out the same whatever order the samples are added in.
+pub(crate) struct Film {
+    width: usize,
+    height: usize,
+    filter: PixelFilter,
+    // Red, green and blue times the weight, then the weight
+    sums: Vec<[FixedPointSum; 4]>,
+}
+
+impl Film {
+    pub(crate) fn new(width: usize, height: usize, filter: PixelFilter) -> Film {
+        Film {
+            width,
+            height,
+            filter,
+            sums: (0..width * height).map(|_| Default::default()).collect(),
+        }
+    }
+
+    // Finite sample at x, y in pixels from the top left corner of the image
+    pub(crate) fn add(&self, x: f64, y: f64, color: Vec3) {
+        for row in self.covered(y, self.height) {
+            let weight_y = self.filter.weight(row as f64 + 0.5 - y);
+
+            for column in self.covered(x, self.width) {
+                let weight = self.filter.weight(column as f64 + 0.5 - x) * weight_y;
+                let values = [color.x() * weight, color.y() * weight, color.z() * weight, weight];
+
+                for (sum, value) in self.sums[row * self.width + column].iter().zip(values) {
+                    sum.add((value * FIXED_POINT_ONE).round().clamp(-MAX_SPLAT, MAX_SPLAT) as i128);
+                }
+            }
+        }
+    }
+
+    // Pixels along one axis with their centers in (position - radius,
+    // position + radius], half open so a box of radius 0.5 covers exactly one
+    fn covered(&self, position: f64, size: usize) -> Range<usize> {
+        let radius = self.filter.radius();
+        let first = (position - radius - 0.5).floor() + 1.;
+        let last = (position + radius - 0.5).floor();
+
+        first.max(0.) as usize..(last + 1.).clamp(0., size as f64) as usize
+    }
+
+    // Weighted mean of every pixel, black without samples. Negative lobes can
+    // ring below black at hard edges, which is cut off.
+    pub(crate) fn framebuffer(&self) -> Framebuffer {
+        let colors = self.sums.iter().map(|sums| {
+            let [red, green, blue, weight] = sums.each_ref().map(|sum| sum.load() as f64 / FIXED_POINT_ONE);
+            if weight > 0. {
+                Vec3::max(&(Vec3::new(red, green, blue) / weight), &Vec3::constant_new(0.))
+            } else {
+                Vec3::constant_new(0.)
+            }
+        });
+
+        Framebuffer::from_colors(self.width, self.height, colors)
+    }
+}
+
+// 128 bit integer that threads add to, there is no AtomicI128. Additions wrap
+// like the halves do, so any order of them gives the same sum.
+#[derive(Default)]
+struct FixedPointSum {
+    low: AtomicU64,
+    high: AtomicU64,
+}
+
+impl FixedPointSum {
+    fn add(&self, value: i128) {
+        let (low, high) = (value as u64, (value >> 64) as u64);
+        let previous = self.low.fetch_add(low, Ordering::Relaxed);
+        let carry = previous.overflowing_add(low).1 as u64;
+        self.high.fetch_add(high.wrapping_add(carry), Ordering::Relaxed);
+    }
+
+    // Only whole once no thread is adding any more
+    fn load(&self) -> i128 {
+        ((self.high.load(Ordering::Relaxed) as u128) << 64 | self.low.load(Ordering::Relaxed) as u128) as i128
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn film() -> Film {
+        Film::new(4, 3, PixelFilter::new(FilterKind::Tent))
+    }
+
+    // Splat positions and colors around pixel 1, 1 that reach into its
+    // neighbours, where the Mitchell filter is negative
+    fn samples() -> Vec<(f64, f64, Vec3)> {
+        (0..64)
+            .map(|i| {
+                let (x, y) = (0.3 + (i % 8) as f64 * 0.3, 0.3 + (i / 8) as f64 * 0.3);
+                let brightness = if i % 5 == 0 { 1e4 } else { 1e-3 * i as f64 };
+                (x, y, Vec3::new(brightness, 0.5, 1. / (i + 1) as f64))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sums_do_not_depend_on_the_order() {
+        let filter = PixelFilter::new(FilterKind::Mitchell);
+        let forward = Film::new(4, 3, filter);
+        let backward = Film::new(4, 3, filter);
+
+        for &(x, y, color) in &samples() {
+            forward.add(x, y, color);
+        }
+        for &(x, y, color) in samples().iter().rev() {
+            backward.add(x, y, color);
+        }
+
+        assert_eq!(forward.framebuffer().pixels(), backward.framebuffer().pixels());
+    }
+
+    #[test]
+    fn dark_pixels_keep_their_precision() {
+        let film = film();
+        film.add(1.5, 1.5, Vec3::new(1e-4, 0.5, 1e-6));
+
+        let [red, green, blue] = film.framebuffer().pixel(1, 1);
+        assert!((red as f64 - 1e-4).abs() < 1e-9, "{}", red);
+        assert_eq!(green, 0.5);
+        assert!((blue as f64 - 1e-6).abs() < 1e-9, "{}", blue);
+    }
+
+    #[test]
+    fn sums_carry_into_the_high_half() {
+        let film = film();
+        // 3e9 in fixed point is past 2^63, four of them past 2^64
+        for _ in 0..4 {
+            film.add(1.5, 1.5, Vec3::new(3e9, 0., 1.));
+        }
+
+        assert_eq!(film.framebuffer().pixel(1, 1), [3e9, 0., 1.]);
+    }
+
+    #[test]
+    fn huge_samples_are_clamped_instead_of_wrapping() {
+        let film = film();
+        for _ in 0..4 {
+            film.add(1.5, 1.5, Vec3::new(1e300, 3e9, 1.));
+        }
+
+        let [red, green, blue] = film.framebuffer().pixel(1, 1);
+        assert!(red > 1e18, "{}", red);
+        assert_eq!(green, 3e9);
+        assert_eq!(blue, 1.);
+    }
+
+    #[test]
+    fn negative_sums_read_back() {
+        let sum = FixedPointSum::default();
+        for value in [-5, i64::MAX as i128, i64::MAX as i128, -(1 << 100), 1 << 100, -(i64::MAX as i128) * 2] {
+            sum.add(value);
+        }
+        assert_eq!(sum.load(), -5);
+    }
+}
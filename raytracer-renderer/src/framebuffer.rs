@@ -0,0 +1,84 @@
+use crate::vec3::Vec3;
+
+use image::{ ImageOutputFormat, ImageResult, Rgb, Rgb32FImage };
+use image::codecs::hdr::HdrEncoder;
+
+use std::io::{ Seek, Write };
+
+// Linear RGB radiance of every pixel, rows from the top. Nothing is clamped
+// or gamma encoded, so it keeps the full range of the render.
+#[derive(Clone)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<[f32; 3]>,
+}
+
+impl Framebuffer {
+    // Black
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![[0.; 3]; width * height],
+        }
+    }
+
+    pub fn from_colors(width: usize, height: usize, colors: impl Iterator<Item = Vec3>) -> Framebuffer {
+        let pixels = colors
+            .map(|color| [color.x() as f32, color.y() as f32, color.z() as f32])
+            .collect::<Vec<_>>();
+        assert_eq!(pixels.len(), width * height, "one color per pixel");
+
+        Framebuffer { width, height, pixels }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[[f32; 3]] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [f32; 3] {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: [f32; 3]) {
+        self.pixels[y * self.width + x] = color;
+    }
+
+    fn to_image(&self) -> Rgb32FImage {
+        Rgb32FImage::from_fn(self.width as u32, self.height as u32, |x, y| Rgb(self.pixel(x as usize, y as usize)))
+    }
+
+    // OpenEXR with 32 bit float channels
+    pub fn write_exr<W: Write + Seek>(&self, writer: &mut W) -> ImageResult<()> {
+        self.to_image().write_to(writer, ImageOutputFormat::OpenExr)
+    }
+
+    // Radiance RGBE, 8 bit mantissas with a shared exponent
+    pub fn write_hdr<W: Write>(&self, writer: &mut W) -> ImageResult<()> {
+        let pixels = self.pixels.iter().map(|&color| Rgb(color)).collect::<Vec<_>>();
+
+        HdrEncoder::new(writer).encode(&pixels, self.width, self.height)
+    }
+
+    // Portable float map, little endian with the rows from the bottom
+    pub fn write_pfm<W: Write>(&self, writer: &mut W) -> ImageResult<()> {
+        // A negative scale marks little endian
+        write!(writer, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for row in self.pixels.chunks(self.width).rev() {
+            for channel in row.iter().flatten() {
+                writer.write_all(&channel.to_le_bytes())?;
+            }
+        }
+
+        Ok(writer.flush()?)
+    }
+}
@@ -1,6 +1,8 @@
 use crate::ray::Ray;
 use crate::vec3::Vec3;
 use crate::material::Material;
+use crate::aabb::Aabb;
+use crate::transform::Transform;
 
 pub struct HitRecord<'a> {
     point: Vec3,
@@ -8,19 +10,45 @@ pub struct HitRecord<'a> {
     pub material: &'a dyn Material,
     t: f64,
     front_face: bool,
+    // Surface coordinates of the hit point
+    u: f64,
+    v: f64,
+    // Set by the world that holds the object, 0 before that
+    object_id: usize,
 }
 
 impl<'a> HitRecord<'a> {
-    pub fn new(point: Vec3, normal: Vec3, material: &'a dyn Material, t: f64, front_face: bool) -> HitRecord {
+    pub fn new(point: Vec3, normal: Vec3, material: &'a dyn Material, t: f64, front_face: bool) -> HitRecord<'a> {
         HitRecord {
             point,
             normal,
             material,
             t,
-            front_face
+            front_face,
+            u: 0.,
+            v: 0.,
+            object_id: 0,
         }
     }
 
+    pub fn with_uv(mut self, u: f64, v: f64) -> HitRecord<'a> {
+        self.u = u;
+        self.v = v;
+        self
+    }
+
+    pub fn with_object_id(mut self, object_id: usize) -> HitRecord<'a> {
+        self.object_id = object_id;
+        self
+    }
+
+    // Moves the hit from object space to world space
+    pub fn transformed(mut self, transform: &Transform) -> HitRecord<'a> {
+        self.point = transform.apply_point(self.point);
+        self.normal = transform.apply_normal(self.normal).unit_vector();
+        self
+    }
+
     pub fn t(&self) -> f64 {
         self.t
     }
@@ -36,9 +64,25 @@ impl<'a> HitRecord<'a> {
     pub fn front_face(&self) -> bool {
         self.front_face
     }
+
+    pub fn u(&self) -> f64 {
+        self.u
+    }
+
+    pub fn v(&self) -> f64 {
+        self.v
+    }
+
+    pub fn object_id(&self) -> usize {
+        self.object_id
+    }
 }
     
 
-pub trait Hittable: Sync {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+// Shared between Instances through Arc, so it has to be Send as well
+pub trait Hittable: Send + Sync {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
+
+    // None for objects without finite extent, they are kept out of the BVH
+    fn bounding_box(&self) -> Option<Aabb>;
 }
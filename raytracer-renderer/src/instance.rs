@@ -0,0 +1,126 @@
+use crate::hittable::{ Hittable, HitRecord };
+use crate::ray::Ray;
+use crate::aabb::Aabb;
+use crate::transform::Transform;
+use crate::animation::KeyframedTransform;
+
+use std::sync::Arc;
+
+// Places a shared object with a transform. Rays are moved into the object's
+// space and hits are moved back out, so the geometry itself is never copied.
+pub struct Instance {
+    object: Arc<dyn Hittable>,
+    transform: Transform,
+    bounding_box: Option<Aabb>,
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Hittable>, transform: Transform) -> Instance {
+        let bounding_box = object.bounding_box().map(|aabb| transform.apply_box(&aabb));
+
+        Instance {
+            object,
+            transform,
+            bounding_box,
+        }
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let inverse = self.transform.inverse();
+        // The direction is left unnormalized so t means the same in both spaces
+        let object_ray = Ray::new(inverse.apply_point(ray.origin()), inverse.apply_vector(ray.direction()))
+            .with_time(ray.time());
+
+        self.object
+            .hit(&object_ray, t_min, t_max)
+            .map(|hit_record| hit_record.transformed(&self.transform))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bounding_box
+    }
+}
+
+// Instance whose transform follows an animation, evaluated at each ray's time
+pub struct AnimatedInstance {
+    object: Arc<dyn Hittable>,
+    animation: KeyframedTransform,
+    bounding_box: Option<Aabb>,
+}
+
+impl AnimatedInstance {
+    pub fn new(object: Arc<dyn Hittable>, animation: KeyframedTransform) -> AnimatedInstance {
+        let bounding_box = object.bounding_box().map(|aabb| animation.apply_box(&aabb));
+
+        AnimatedInstance {
+            object,
+            animation,
+            bounding_box,
+        }
+    }
+}
+
+impl Hittable for AnimatedInstance {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let transform = self.animation.at(ray.time());
+        let inverse = transform.inverse();
+        let object_ray = Ray::new(inverse.apply_point(ray.origin()), inverse.apply_vector(ray.direction()))
+            .with_time(ray.time());
+
+        self.object
+            .hit(&object_ray, t_min, t_max)
+            .map(|hit_record| hit_record.transformed(&transform))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bounding_box
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::material::{ Lambertian, Material };
+    use crate::sphere::Sphere;
+    use crate::vec3::Vec3;
+
+    fn unit_sphere() -> Arc<dyn Hittable> {
+        let material: Arc<dyn Material> = Arc::new(Lambertian::new(Vec3::constant_new(0.5)));
+        Arc::new(Sphere::new(Vec3::constant_new(0.), 1., material))
+    }
+
+    #[test]
+    fn normals_of_stretched_objects_use_the_inverse_transpose() {
+        // Ellipsoid x^2 / 4 + y^2 + z^2 = 1
+        let instance = Instance::new(unit_sphere(), Transform::scaling(Vec3::new(2., 1., 1.)));
+        let point = Vec3::new(2f64.sqrt(), 0.5f64.sqrt(), 0.);
+        // Gradient of the implicit surface, not the stretched sphere normal
+        let normal = Vec3::new(point.x() / 4., point.y(), 0.).unit_vector();
+
+        let hit_record = instance.hit(&Ray::new(point + normal * 3., -normal), 0.001, f64::INFINITY).expect("the ray hits");
+
+        assert!((hit_record.point() - point).length() < 1e-9, "{:?}", hit_record.point());
+        assert!((hit_record.normal() - normal).length() < 1e-9, "{:?}", hit_record.normal());
+        assert!((hit_record.t() - 3.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn rotated_instances_are_bounded() {
+        let instance = Instance::new(unit_sphere(), Transform::rotation(Vec3::new(0., 1., 0.), 45.));
+        let aabb = instance.bounding_box().expect("spheres are bounded");
+
+        let half_diagonal = 2f64.sqrt();
+        assert!((aabb.maximum() - Vec3::new(half_diagonal, 1., half_diagonal)).length() < 1e-9, "{:?}", aabb.maximum());
+        assert!((aabb.minimum() + Vec3::new(half_diagonal, 1., half_diagonal)).length() < 1e-9, "{:?}", aabb.minimum());
+
+        // Hits anywhere on the sphere stay inside the box
+        for direction in [Vec3::new(1., 0.2, 0.3), Vec3::new(-0.4, -1., 0.1), Vec3::new(0.3, 0.3, -1.)] {
+            let hit_record = instance.hit(&Ray::new(direction * -5., direction), 0.001, f64::INFINITY).expect("the ray hits");
+            let point = hit_record.point();
+            assert!((0..3).all(|axis| point[axis] >= aabb.minimum()[axis] && point[axis] <= aabb.maximum()[axis]));
+        }
+    }
+}
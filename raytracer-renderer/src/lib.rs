@@ -1,131 +1,365 @@
 pub mod vec3;
 pub mod sphere;
+pub mod plane;
+pub mod rect;
+pub mod instance;
+pub mod medium;
+pub mod transform;
+pub mod animation;
+pub mod triangle;
 pub mod world;
 pub mod camera;
 pub mod material;
+pub mod texture;
+pub mod obj;
+pub mod ray;
+pub mod hittable;
+pub mod aabb;
+pub mod settings;
+pub mod background;
+pub mod scene;
+pub mod rng;
+pub mod sampler;
+pub mod adaptive;
+pub mod progressive;
+pub mod tile;
+pub mod observer;
+pub mod cancel;
+pub mod framebuffer;
+pub mod tonemap;
+pub mod output;
+pub mod aov;
+pub mod denoise;
+pub mod filter;
 
-mod ray;
-mod hittable;
 mod utils;
+mod bvh;
+mod perlin;
 
 use ray::Ray;
 use vec3::Vec3;
 use world::World;
 use camera::Camera;
-use utils::{ random_double, random_double_range };
-use material::{ Material, Lambertian, Metal, Dielectric };
-use sphere::Sphere;
+use settings::RenderSettings;
+use background::Background;
+use scene::random_scene_description;
+use sampler::Sampler;
+use adaptive::PixelStatistics;
+use tile::{ Tile, tiles };
+use observer::{ RenderObserver, ProgressTracker };
+use cancel::{ CancellationToken, RenderStatus, StopCheck };
+use framebuffer::Framebuffer;
+use output::{ ImageOutput, OutputError };
+use aov::{ Aov, AovBuffer, AovPixel, AovSample, aov_buffers };
+use filter::Film;
+
+use std::path::Path;
 
-use image;
 use rayon::prelude::*;
-use std::sync::Arc;
 
-fn ray_color(ray: &Ray, world: &World, depth_limit: usize) -> Vec3 {
-    if depth_limit <= 0 {
+fn ray_color(ray: &Ray, world: &World, background: &Background, depth_limit: usize,
+             sampler: &mut dyn Sampler) -> Vec3 {
+    if depth_limit == 0 {
         return Vec3::constant_new(0.);
     }
 
     match world.did_hit(ray, 0.001, f64::INFINITY) {
         Some(hit_record) => {
-            if let Some((scattered, attenuation)) = hit_record.material.scatter(ray, &hit_record) {
-                ray_color(&scattered, world, depth_limit - 1) * attenuation 
+            let emitted = hit_record.material.emitted(&hit_record);
+
+            if let Some((scattered, attenuation)) = hit_record.material.scatter(ray, &hit_record, sampler) {
+                emitted + ray_color(&scattered, world, background, depth_limit - 1, sampler) * attenuation 
             } else {
-                Vec3::constant_new(0.)
+                emitted
             }
         }
-        None => {
-            // Linear interpolation from 0 - 1
-            let t = (ray.direction().unit_vector().y() + 1.) * 0.5;
+        None => background.color(ray),
+    }
+}
+
+// Color of one sample of pixel i, also added to aovs and splatted into the
+// film if given. Samples only depend on the seed, pixel and sample index, so
+// they can be taken in any order. None for NaN or infinite colors, which are
+// left out of the film and the pixel alike.
+#[allow(clippy::too_many_arguments)]
+fn render_sample(settings: &RenderSettings, world: &World, camera: &Camera, sampler: &mut dyn Sampler,
+                 i: usize, sample: usize, aovs: Option<&mut AovPixel>, film: Option<&Film>) -> Option<Vec3> {
+    let RenderSettings { image_width, image_height, max_depth, .. } = *settings;
+    let (col, row)  = (i % image_width, i / image_width);
+
+    sampler.start_sample(i, sample);
+    let (jitter_u, jitter_v) = sampler.get_2d();
+    let u = (col as f64 + jitter_u) / (image_width - 1) as f64;
+    let v = 1. - (row as f64 + jitter_v) / (image_height - 1) as f64;
+
+    let ray = camera.get_ray(u, v, sampler);
+    if let Some(aovs) = aovs {
+        aovs.add(&AovSample::trace(&ray, world, camera, &settings.background));
+    }
+    let color = ray_color(&ray, world, &settings.background, max_depth, sampler);
+    if !(color.x().is_finite() && color.y().is_finite() && color.z().is_finite()) {
+        return None;
+    }
+    if let Some(film) = film {
+        film.add(col as f64 + jitter_u, row as f64 + jitter_v, color);
+    }
+    Some(color)
+}
+
+// Whether the pixel has all the samples it gets
+fn is_pixel_done(settings: &RenderSettings, statistics: &PixelStatistics) -> bool {
+    statistics.count() >= settings.samples_per_pixel
+        || settings.adaptive.is_some_and(|adaptive| statistics.is_converged(&adaptive))
+}
+
+// Mean of the samples of pixel i, and how many were taken. Fewer than it
+// should get if the render was stopped, none if it already was. The AOVs stay
+// empty unless renders_aovs.
+fn render_pixel(settings: &RenderSettings, world: &World, camera: &Camera, stop: &StopCheck, film: Option<&Film>,
+                renders_aovs: bool, i: usize) -> (PixelStatistics, AovPixel) {
+    let mut sampler = settings.sampler.sampler(settings.seed, settings.samples_per_pixel);
+    let mut statistics = PixelStatistics::default();
+    let mut aovs = AovPixel::default();
+
+    // Samples are added in order, a parallel sum would make the floating
+    // point result depend on the thread scheduling
+    while !is_pixel_done(settings, &statistics) && !stop.should_stop() {
+        let sample = statistics.count();
+        let aovs = renders_aovs.then_some(&mut aovs);
+        match render_sample(settings, world, camera, &mut *sampler, i, sample, aovs, film) {
+            Some(color) => statistics.add(color),
+            None => statistics.skip(),
+        }
+    }
+
+    (statistics, aovs)
+}
+
+pub struct RenderOutput {
+    pub framebuffer: Framebuffer,
+    // Samples taken per pixel, less than samples_per_pixel for pixels that
+    // converged early with adaptive sampling
+    pub sample_counts: Vec<usize>,
+    // One for each of settings.aovs, in that order
+    pub aovs: Vec<AovBuffer>,
+    // Pixels of a render that stopped early may be missing samples, or be
+    // black without any
+    pub status: RenderStatus,
+}
+
+impl RenderOutput {
+    // rendered_aovs are settings.rendered_aovs()
+    fn from_pixels(rendered_aovs: &[Aov], width: usize, height: usize, pixels: &[PixelStatistics],
+                   aov_pixels: &[AovPixel], status: RenderStatus) -> RenderOutput {
+        RenderOutput {
+            framebuffer: Framebuffer::from_colors(width, height, pixels.iter().map(PixelStatistics::mean)),
+            sample_counts: pixels.iter().map(PixelStatistics::count).collect(),
+            aovs: aov_buffers(rendered_aovs, width, height, aov_pixels),
+            status,
+        }
+    }
 
-            // The more ray point upwards, the bluer the image
-            Vec3::new(1.0, 1.0, 1.0) * (-t + 1.0) + Vec3::new(0.5, 0.7, 1.0) * t
+    // Filtered from the film if there is one and denoised if the settings ask
+    // for it, without the AOVs only the denoiser wanted
+    fn finished(mut self, settings: &RenderSettings, film: Option<&Film>) -> RenderOutput {
+        if let Some(film) = film {
+            self.framebuffer = film.framebuffer();
+        }
+        if let Some(denoising) = &settings.denoise {
+            let aov = |aov: Aov| &self.aovs.iter().find(|buffer| buffer.aov == aov).expect("rendered for the denoiser").framebuffer;
+            self.framebuffer = denoising.denoise(&self.framebuffer, aov(Aov::Albedo), aov(Aov::Normal));
         }
+
+        self.aovs.retain(|buffer| settings.aovs.contains(&buffer.aov));
+        self
     }
 }
 
-pub fn raytrace_buffer(image_width: usize, image_height: usize, samples_per_pixel: usize, 
-                       max_depth: usize, world: &World, camera: &Camera, callback: Option<&(dyn Fn(String) + Sync)>) -> Vec<u8> {
-    (0..image_width*image_height)
-        .into_par_iter()
-        .flat_map(|i| {
-            let (col, row)  = (i % image_width, i / image_width);
-
-            let pixel_color: Vec3 = 
-                (0..samples_per_pixel)
-                    .into_par_iter()
-                    .map(|_| {
-                        let u = (col as f64 + random_double()) / (image_width - 1) as f64;
-                        let v = 1. - (row as f64 + random_double()) / (image_height - 1) as f64;
-
-                        let ray = camera.get_ray(u, v);
-                        ray_color(&ray, &world, max_depth)
-                    })
-                    .sum();
-
-            if let Some(callback) = callback {
-            //(callback)(String::from("callback"));
-                (callback)(String::from("abc"));
+// A finished part of the image, handed out while the rest still renders
+pub struct RenderedTile {
+    pub tile: Tile,
+    // Just the tile, its top left pixel is tile.x, tile.y of the image. Always
+    // the mean of the samples of each pixel, filtering needs the neighbouring
+    // tiles too.
+    pub framebuffer: Framebuffer,
+    pub sample_counts: Vec<usize>,
+    // Also the ones the denoiser needs, tiles are not denoised
+    pub aovs: Vec<AovBuffer>,
+}
+
+// Renders the image in tiles of settings.tile_size, several at a time, and
+// hands every finished tile to the observer. Cancelling or running out of
+// settings.time_limit returns what was rendered up to then.
+pub fn raytrace_output(settings: &RenderSettings, world: &World, camera: &Camera,
+                       observer: &dyn RenderObserver, cancel: &CancellationToken) -> RenderOutput {
+    let RenderSettings { image_width, image_height, samples_per_pixel, tile_size, tile_order, time_limit, .. } = *settings;
+
+    let tracker = ProgressTracker::new(observer, image_width * image_height, samples_per_pixel);
+    let stop = StopCheck::new(cancel.clone(), time_limit);
+    // Samples near the edge of a tile are splatted into the tiles around it
+    let film = (!settings.filter.is_pixel_mean()).then(|| Film::new(image_width, image_height, settings.filter));
+    let rendered_aovs = settings.rendered_aovs();
+    observer.started(&tracker.progress());
+
+    // par_bridge hands the tiles out in order, a parallel iterator over the
+    // Vec would split it into halves and start both at once
+    let rendered = tiles(image_width, image_height, tile_size, tile_order)
+        .into_iter()
+        .par_bridge()
+        .map(|tile| {
+            let started_stopped = stop.should_stop();
+            let (pixels, aov_pixels): (Vec<PixelStatistics>, Vec<AovPixel>) = tile
+                .pixel_indices(image_width)
+                .map(|i| render_pixel(settings, world, camera, &stop, film.as_ref(), !rendered_aovs.is_empty(), i))
+                .unzip();
+
+            let RenderOutput { framebuffer, sample_counts, aovs, .. } =
+                RenderOutput::from_pixels(&rendered_aovs, tile.width, tile.height, &pixels, &aov_pixels, RenderStatus::Completed);
+            let rendered = RenderedTile { tile, framebuffer, sample_counts, aovs };
+            // Tiles left after stopping are empty
+            if !started_stopped {
+                tracker.add(tile.pixel_count(), rendered.sample_counts.iter().sum::<usize>() as u64);
+                observer.tile_finished(&rendered, &tracker.progress());
             }
 
-            (pixel_color / samples_per_pixel as f64).rgb()
+            rendered
         })
-        .collect::<Vec<u8>>()
+        .collect::<Vec<RenderedTile>>();
+
+    let mut output = RenderOutput {
+        framebuffer: Framebuffer::new(image_width, image_height),
+        sample_counts: vec![0; image_width * image_height],
+        aovs: rendered_aovs
+            .iter()
+            .map(|&aov| AovBuffer { aov, framebuffer: Framebuffer::new(image_width, image_height) })
+            .collect(),
+        status: stop.status(),
+    };
+    for RenderedTile { tile, framebuffer, sample_counts, aovs } in rendered {
+        for (tile_index, i) in tile.pixel_indices(image_width).enumerate() {
+            let (x, y) = (tile_index % tile.width, tile_index / tile.width);
+            output.framebuffer.set_pixel(tile.x + x, tile.y + y, framebuffer.pixel(x, y));
+            output.sample_counts[i] = sample_counts[tile_index];
+            for (output_aov, tile_aov) in output.aovs.iter_mut().zip(&aovs) {
+                output_aov.framebuffer.set_pixel(tile.x + x, tile.y + y, tile_aov.framebuffer.pixel(x, y));
+            }
+        }
+    }
+
+    match output.status {
+        RenderStatus::Completed => observer.completed(&tracker.progress()),
+        status => observer.stopped(status, &tracker.progress()),
+    }
+    output.finished(settings, film.as_ref())
 }
 
-pub fn raytrace(name: &str, image_width: usize, image_height: usize, samples_per_pixel: usize, 
-                max_depth: usize, world: &World, camera: &Camera) {
-    let buffer = raytrace_buffer(image_width, image_height, samples_per_pixel, max_depth, world, camera, None);
-    
-    image::save_buffer(name, &buffer[..], image_width as u32, image_height as u32, image::ColorType::Rgb8).unwrap();
+pub fn raytrace_buffer(settings: &RenderSettings, world: &World, camera: &Camera,
+                       observer: &dyn RenderObserver) -> Vec<u8> {
+    let framebuffer = raytrace_output(settings, world, camera, observer, &CancellationToken::new()).framebuffer;
+    settings.tone_mapping.to_rgb8(&framebuffer)
 }
 
+// Renders to a file in the format its extension names
+pub fn raytrace(name: &str, settings: &RenderSettings, world: &World, camera: &Camera) -> Result<(), OutputError> {
+    let output = ImageOutput::for_path(name)?;
+    let framebuffer = raytrace_output(settings, world, camera, &(), &CancellationToken::new()).framebuffer;
+
+    output.save(name, &framebuffer, &settings.tone_mapping)
+}
+
+// The classic cover scene with a fresh random layout, see
+// scene::random_scene_description
 pub fn random_scene(number: isize) -> World<'static> {
-    let mut world = World::new();
-
-    let material_ground = Lambertian{color: Vec3::new(0.5, 0.5, 0.5)};
-
-    let ground_sphere = Sphere::new(
-        Vec3::new(0., -1000., 0.),
-        1000.,
-        Box::new(material_ground),
-    );
-
-    world.add(Box::new(ground_sphere));
-
-    for a in -number..number {
-        for b in -number..number {
-            let material_selector = random_double();
-            let center_point = Vec3::new(a as f64 + 0.9*random_double(), 0.2, b as f64 + 0.9*random_double());
-
-            if (center_point - Vec3::new(4., 0.2, 0.)).length() > 0.9 {
-                let material: Box<dyn Material>;
-
-                if material_selector < 0.8 {
-                    let color = Vec3::random() * Vec3::random();
-                    material = Box::new(Lambertian { color });
-                } else if material_selector < 0.95 {
-                    let color = Vec3::random_range(0.5, 1.);
-                    let fuzz = random_double_range(0., 0.5);
-                    material = Box::new(Metal::new(color, fuzz));
-                } else {
-                    let sphere_material = Dielectric::new(1.5);
-                    material = Box::new(sphere_material);
-                }
-
-                let generated_sphere = Box::new(Sphere::new(center_point, 0.2, material));
-                world.add(generated_sphere);
+    random_scene_description(number, rand::random())
+        .build_world(Path::new(""))
+        .expect("the random scene description is valid")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use filter::{ FilterKind, PixelFilter };
+    use tile::TileOrder;
+    use adaptive::AdaptiveSampling;
+
+    use std::time::{ Duration, Instant };
+
+    fn render(settings: &RenderSettings, threads: usize, cancel: &CancellationToken) -> RenderOutput {
+        let world = random_scene_description(2, 1)
+            .build_world(Path::new(""))
+            .expect("the random scene description is valid");
+        let camera = Camera::new(Vec3::new(13., 2., 3.), Vec3::new(0., 0., 0.), Vec3::new(0., 1., 0.), 20.,
+                                 settings.aspect_ratio(), 0.1, 10.);
+
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .expect("a thread pool")
+            .install(|| raytrace_output(settings, &world, &camera, &(), cancel))
+    }
+
+    // Tiles and threads only change which pixel is rendered when, not the
+    // samples it gets
+    #[test]
+    fn threads_tile_sizes_and_orders_render_the_same_image() {
+        let mut settings = RenderSettings::new(37, 23, 8, 10);
+        settings.seed = 5;
+        settings.adaptive = Some(AdaptiveSampling { noise_threshold: 0.05, min_samples: 4 });
+
+        for filter in [PixelFilter::default(), PixelFilter::new(FilterKind::Gaussian)] {
+            settings.filter = filter;
+            settings.tile_size = 64;
+            settings.tile_order = TileOrder::Scanline;
+            let expected = render(&settings, 1, &CancellationToken::new());
+
+            for (threads, tile_size, tile_order) in [(4, 7, TileOrder::Spiral), (3, 16, TileOrder::Hilbert), (8, 1, TileOrder::Scanline)] {
+                settings.tile_size = tile_size;
+                settings.tile_order = tile_order;
+                let output = render(&settings, threads, &CancellationToken::new());
+
+                assert_eq!(output.framebuffer.pixels(), expected.framebuffer.pixels(), "{} threads, {} pixel {:?} tiles", threads, tile_size, tile_order);
+                assert_eq!(output.sample_counts, expected.sample_counts);
             }
         }
     }
 
-    let material1 = Box::new(Dielectric::new(1.5));
-    world.add(Box::new(Sphere::new(Vec3::new(0., 1., 0.), 1., material1)));
+    // Far more samples than the test waits for, with some pixels done by the
+    // time it stops
+    fn assert_stopped_early(output: &RenderOutput, settings: &RenderSettings, started: Instant) {
+        assert!(started.elapsed() < Duration::from_secs(10), "took {:?}", started.elapsed());
+        assert!(output.sample_counts.iter().all(|&count| count < settings.samples_per_pixel));
+        assert!(output.sample_counts.iter().any(|&count| count > 0));
+        assert!(output.framebuffer.pixels().iter().any(|pixel| pixel.iter().any(|&value| value > 0.)));
+    }
+
+    #[test]
+    fn cancelled_renders_return_what_they_have() {
+        let settings = RenderSettings::new(16, 12, 1_000_000, 10);
+        let cancel = CancellationToken::new();
+        let started = Instant::now();
 
-    let material2 = Box::new(Lambertian { color: Vec3::new(0.4, 0.2, 0.1) });
-    world.add(Box::new(Sphere::new(Vec3::new(-4., 1., 0.), 1., material2)));
+        let output = std::thread::scope(|scope| {
+            let canceller = cancel.clone();
+            scope.spawn(move || {
+                std::thread::sleep(Duration::from_millis(100));
+                canceller.cancel();
+            });
+            render(&settings, 2, &cancel)
+        });
 
-    let material3 = Box::new(Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.0));
-    world.add(Box::new(Sphere::new(Vec3::new(4., 1., 0.), 1., material3)));
+        assert_eq!(output.status, RenderStatus::Cancelled);
+        assert_stopped_early(&output, &settings, started);
+    }
 
-    world
+    #[test]
+    fn timed_out_renders_return_what_they_have() {
+        let mut settings = RenderSettings::new(16, 12, 1_000_000, 10);
+        settings.time_limit = Some(Duration::from_millis(100));
+        let started = Instant::now();
+
+        let output = render(&settings, 2, &CancellationToken::new());
+
+        assert_eq!(output.status, RenderStatus::TimedOut);
+        assert_stopped_early(&output, &settings, started);
+    }
 }
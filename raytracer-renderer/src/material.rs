@@ -1,51 +1,91 @@
 use crate::ray::Ray;
 use crate::hittable::HitRecord;
 use crate::vec3::Vec3;
-use crate::utils::random_double;
+use crate::sampler::Sampler;
+use crate::texture::{ Texture, SolidColor };
 
-pub trait Material: Sync {
-    // Returns scattered ray and color
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Ray, Vec3)>;
+use std::sync::Arc;
+
+// Shared between objects through Arc, so it has to be Send as well
+pub trait Material: Send + Sync {
+    // Returns scattered ray and color, all randomness comes from sampler
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<(Ray, Vec3)>;
+
+    // Light given off at the hit point, black for everything but lights
+    fn emitted(&self, _hit_record: &HitRecord) -> Vec3 {
+        Vec3::constant_new(0.)
+    }
+
+    // Surface color without any lighting, white for clear materials
+    fn albedo(&self, _hit_record: &HitRecord) -> Vec3 {
+        Vec3::constant_new(1.)
+    }
 }
 
 pub struct Lambertian {
-    pub color: Vec3
+    albedo: Arc<dyn Texture>,
+}
+
+impl Lambertian {
+    pub fn new(color: Vec3) -> Lambertian {
+        Lambertian::textured(Arc::new(SolidColor::new(color)))
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>) -> Lambertian {
+        Lambertian {
+            albedo
+        }
+    }
 }
 
 impl Material for Lambertian {
-    fn scatter(&self, _ray: &Ray, hit_record: &HitRecord) -> Option<(Ray, Vec3)> {
-        let scatter_direction = hit_record.normal() + Vec3::random_unit_vector();
-        Some((Ray::new(hit_record.point(), scatter_direction), self.color))
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<(Ray, Vec3)> {
+        let scatter_direction = hit_record.normal() + Vec3::random_unit_vector(sampler);
+        let color = self.albedo.value(hit_record.u(), hit_record.v(), hit_record.point());
+        Some((Ray::new(hit_record.point(), scatter_direction).with_time(ray.time()), color))
+    }
+
+    fn albedo(&self, hit_record: &HitRecord) -> Vec3 {
+        self.albedo.value(hit_record.u(), hit_record.v(), hit_record.point())
     }
 }
 
 pub struct Metal {
-    color: Vec3,
+    albedo: Arc<dyn Texture>,
     fuzziness: f64,
 }
 
 impl Metal {
     pub fn new(color: Vec3, fuzziness: f64) -> Metal {
+        Metal::textured(Arc::new(SolidColor::new(color)), fuzziness)
+    }
+
+    pub fn textured(albedo: Arc<dyn Texture>, fuzziness: f64) -> Metal {
         let fuzziness = if fuzziness < 1. { fuzziness } else { 1. };
         Metal {
-            color,
+            albedo,
             fuzziness
         }
     }
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<(Ray, Vec3)> {
         let reflected_vector = Vec3::reflect(ray.direction().unit_vector(), hit_record.normal());
-        let scattered = Ray::new(hit_record.point(), reflected_vector + Vec3::random_in_unit_sphere() * self.fuzziness); 
+        let scattered = Ray::new(hit_record.point(), reflected_vector + Vec3::random_in_unit_sphere(sampler) * self.fuzziness)
+            .with_time(ray.time());
 
         // TODO: Why is there a > 0. if statement?
         if Vec3::dot(&scattered.direction(), &hit_record.normal()) > 0. {
-            Some((scattered, self.color))
+            Some((scattered, self.albedo.value(hit_record.u(), hit_record.v(), hit_record.point())))
         } else {
             None
         }
     }
+
+    fn albedo(&self, hit_record: &HitRecord) -> Vec3 {
+        self.albedo.value(hit_record.u(), hit_record.v(), hit_record.point())
+    }
 }
 
 pub struct Dielectric {
@@ -68,38 +108,79 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray: &Ray, hit_record: &HitRecord) -> Option<(Ray, Vec3)> {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<(Ray, Vec3)> {
         let refraction_ratio = if hit_record.front_face() { 1.0/self.ir } else { self.ir };
         let unit_direction = ray.direction().unit_vector();
 
         let cos_theta = f64::min(Vec3::dot(&(-unit_direction), &hit_record.normal()), 1.);
         let sin_theta = f64::sqrt(1. - cos_theta * cos_theta);
 
-        let direction: Vec3;
-
-        if refraction_ratio * sin_theta > 1. || Dielectric::reflectance(cos_theta, refraction_ratio) > random_double() { 
-            direction = Vec3::reflect(unit_direction, hit_record.normal());
+        let direction = if refraction_ratio * sin_theta > 1. || Dielectric::reflectance(cos_theta, refraction_ratio) > sampler.get_1d() { 
+            Vec3::reflect(unit_direction, hit_record.normal())
         } else {
-            direction = Vec3::refract(unit_direction, hit_record.normal(), refraction_ratio);
-        }
+            Vec3::refract(unit_direction, hit_record.normal(), refraction_ratio)
+        };
 
-        let scattered_ray = Ray::new(hit_record.point(), direction); 
+        let scattered_ray = Ray::new(hit_record.point(), direction).with_time(ray.time());
 
         Some((scattered_ray, Vec3::constant_new(1.0)))
     }
 }
 
+// Area light, any geometry with this material emits color * intensity and
+// reflects nothing
+pub struct DiffuseLight {
+    color: Vec3,
+    intensity: f64,
+}
 
+impl DiffuseLight {
+    pub fn new(color: Vec3, intensity: f64) -> DiffuseLight {
+        DiffuseLight {
+            color,
+            intensity,
+        }
+    }
+}
 
+impl Material for DiffuseLight {
+    fn scatter(&self, _ray: &Ray, _hit_record: &HitRecord, _sampler: &mut dyn Sampler) -> Option<(Ray, Vec3)> {
+        None
+    }
 
+    fn emitted(&self, _hit_record: &HitRecord) -> Vec3 {
+        self.color * self.intensity
+    }
 
+    fn albedo(&self, _hit_record: &HitRecord) -> Vec3 {
+        self.color
+    }
+}
 
+// Phase function of a ConstantMedium, scatters the same in every direction
+pub struct Isotropic {
+    albedo: Arc<dyn Texture>,
+}
 
+impl Isotropic {
+    pub fn new(color: Vec3) -> Isotropic {
+        Isotropic::textured(Arc::new(SolidColor::new(color)))
+    }
 
+    pub fn textured(albedo: Arc<dyn Texture>) -> Isotropic {
+        Isotropic {
+            albedo
+        }
+    }
+}
 
+impl Material for Isotropic {
+    fn scatter(&self, ray: &Ray, hit_record: &HitRecord, sampler: &mut dyn Sampler) -> Option<(Ray, Vec3)> {
+        let color = self.albedo.value(hit_record.u(), hit_record.v(), hit_record.point());
+        Some((Ray::new(hit_record.point(), Vec3::random_unit_vector(sampler)).with_time(ray.time()), color))
+    }
 
-
-
-
-
-
+    fn albedo(&self, hit_record: &HitRecord) -> Vec3 {
+        self.albedo.value(hit_record.u(), hit_record.v(), hit_record.point())
+    }
+}
@@ -0,0 +1,128 @@
+use crate::hittable::{ Hittable, HitRecord };
+use crate::vec3::Vec3;
+use crate::ray::Ray;
+use crate::material::Material;
+use crate::aabb::Aabb;
+use crate::rng::hash_to_unit;
+
+use std::sync::Arc;
+
+// Gap between the entry hit and the search for the exit hit, so the entry
+// point is not found again
+const EXIT_EPSILON: f64 = 1e-4;
+
+// Fog or smoke filling a boundary with constant density. Rays scatter after a
+// random distance with probability density * distance, using phase_function
+// as the material at that point. The boundary has to be convex, its own
+// material is ignored.
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hittable>,
+    neg_inv_density: f64,
+    phase_function: Arc<dyn Material>,
+}
+
+impl ConstantMedium {
+    pub fn new(boundary: Arc<dyn Hittable>, density: f64, phase_function: Arc<dyn Material>) -> ConstantMedium {
+        ConstantMedium {
+            boundary,
+            neg_inv_density: -1. / density,
+            phase_function,
+        }
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // Both boundary crossings along the whole line, the entry is behind
+        // the origin for rays that start inside
+        let entry = self.boundary.hit(ray, f64::NEG_INFINITY, f64::INFINITY)?.t();
+        let exit = self.boundary.hit(ray, entry + EXIT_EPSILON, f64::INFINITY)?.t();
+
+        let entry = f64::max(entry, t_min);
+        let exit = f64::min(exit, t_max);
+        if entry >= exit {
+            return None;
+        }
+
+        let ray_length = ray.direction().length();
+        let distance_inside = (exit - entry) * ray_length;
+        // Hittables get no generator, the ray itself is random enough to
+        // seed the distance
+        let origin = ray.origin();
+        let direction = ray.direction();
+        let random = hash_to_unit(&[origin.x(), origin.y(), origin.z(), direction.x(), direction.y(), direction.z(), ray.time()]);
+        let hit_distance = self.neg_inv_density * random.ln();
+        if hit_distance > distance_inside {
+            return None;
+        }
+
+        let t = entry + hit_distance / ray_length;
+
+        // Scattering does not depend on the normal, any direction will do
+        Some(HitRecord::new(ray.at(t), Vec3::new(1., 0., 0.), &*self.phase_function, t, true))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::material::Isotropic;
+    use crate::sphere::Sphere;
+
+    fn fog(density: f64) -> ConstantMedium {
+        let phase_function: Arc<dyn Material> = Arc::new(Isotropic::new(Vec3::constant_new(0.5)));
+        let boundary = Arc::new(Sphere::new(Vec3::constant_new(0.), 1., phase_function.clone()));
+        ConstantMedium::new(boundary, density, phase_function)
+    }
+
+    // Rays starting at the center, so every one of them starts inside
+    fn directions() -> impl Iterator<Item = Vec3> {
+        (0..64).map(|i| {
+            let angle = i as f64 * 0.7;
+            Vec3::new(angle.cos(), (i as f64 * 0.31).sin(), angle.sin())
+        })
+    }
+
+    #[test]
+    fn rays_starting_inside_scatter_ahead_of_their_origin() {
+        let medium = fog(1000.);
+
+        for direction in directions() {
+            let ray = Ray::new(Vec3::constant_new(0.), direction);
+            let hit_record = medium.hit(&ray, 0.001, f64::INFINITY).expect("dense fog scatters every ray");
+            let distance = (hit_record.t() - 0.001) * direction.length();
+
+            assert!(hit_record.t() >= 0.001);
+            assert!(distance < 0.05, "{}", distance);
+            assert!(hit_record.point().length() < 1.);
+        }
+    }
+
+    #[test]
+    fn thin_fog_lets_rays_leave() {
+        let medium = fog(1e-9);
+
+        for direction in directions() {
+            assert!(medium.hit(&Ray::new(Vec3::constant_new(0.), direction), 0.001, f64::INFINITY).is_none());
+        }
+    }
+
+    #[test]
+    fn rays_only_scatter_within_their_range() {
+        let medium = fog(1000.);
+        let direction = Vec3::new(1., 0., 0.);
+
+        // Starting inside but ending before the fog begins to count
+        assert!(medium.hit(&Ray::new(Vec3::constant_new(0.), direction), 0.001, 0.001).is_none());
+        // Pointing away from the fog
+        assert!(medium.hit(&Ray::new(Vec3::new(2., 0., 0.), direction), 0.001, f64::INFINITY).is_none());
+        // Coming from outside, the scattering starts at the boundary
+        let hit_record = medium.hit(&Ray::new(Vec3::new(-3., 0., 0.), direction), 0.001, f64::INFINITY).expect("the ray enters");
+        assert!(hit_record.t() >= 2. && hit_record.t() < 2.05, "{}", hit_record.t());
+    }
+}
@@ -0,0 +1,588 @@
+use crate::material::{ Material, Lambertian, Metal, Dielectric };
+use crate::triangle::TriangleMesh;
+use crate::vec3::Vec3;
+use crate::world::World;
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io { path: PathBuf, source: io::Error },
+    Parse { path: PathBuf, line: usize, message: String },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ObjError::Parse { path, line, message } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObjError::Io { source, .. } => Some(source),
+            ObjError::Parse { .. } => None,
+        }
+    }
+}
+
+// Material entry of an .mtl library
+#[derive(Clone, Debug)]
+pub struct ObjMaterial {
+    pub name: String,
+    // Kd
+    pub diffuse: Vec3,
+    // Ks
+    pub specular: Vec3,
+    // Ns, Phong exponent
+    pub shininess: f64,
+    // Ni
+    pub ior: f64,
+    // d, or 1 - Tr
+    pub dissolve: f64,
+    pub illum: u32,
+}
+
+impl ObjMaterial {
+    fn new(name: &str) -> ObjMaterial {
+        ObjMaterial {
+            name: name.to_string(),
+            diffuse: Vec3::constant_new(0.8),
+            specular: Vec3::constant_new(0.),
+            shininess: 0.,
+            ior: 1.5,
+            dissolve: 1.,
+            illum: 2,
+        }
+    }
+
+    // Transparent materials become glass, materials whose specular color
+    // outweighs the diffuse one (or that ask for reflections) become metal
+    pub fn to_material(&self) -> Arc<dyn Material> {
+        let max_component = |c: Vec3| f64::max(c.x(), f64::max(c.y(), c.z()));
+
+        if self.dissolve < 1. || matches!(self.illum, 4 | 6 | 7 | 9) {
+            Arc::new(Dielectric::new(self.ior))
+        } else if max_component(self.specular) > max_component(self.diffuse) || matches!(self.illum, 3 | 5 | 8) {
+            // Map the Phong exponent onto a roughness
+            let fuzz = f64::sqrt(2. / (self.shininess + 2.));
+            Arc::new(Metal::new(self.specular, fuzz))
+        } else {
+            Arc::new(Lambertian::new(self.diffuse))
+        }
+    }
+}
+
+// Triangles of one group sharing one material
+pub struct ObjMesh {
+    pub group: String,
+    pub material: ObjMaterial,
+    pub mesh: TriangleMesh,
+}
+
+// Problem in a file that was loaded anyway
+#[derive(Debug)]
+pub struct ObjWarning {
+    pub path: PathBuf,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ObjWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.path.display(), self.line, self.message)
+    }
+}
+
+pub struct ObjMeshes {
+    pub meshes: Vec<ObjMesh>,
+    pub warnings: Vec<ObjWarning>,
+}
+
+// Reads an .obj file and its material libraries into a World, one mesh per
+// group and material
+pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<(World<'static>, Vec<ObjWarning>), ObjError> {
+    let ObjMeshes { meshes, warnings } = load_meshes(path)?;
+    let mut world = World::new();
+
+    for obj_mesh in meshes {
+        world.register_material(obj_mesh.mesh.material());
+        world.add(Box::new(obj_mesh.mesh));
+    }
+
+    Ok((world, warnings))
+}
+
+pub fn load_meshes<P: AsRef<Path>>(path: P) -> Result<ObjMeshes, ObjError> {
+    let path = path.as_ref();
+    let source = read(path)?;
+
+    let mut positions: Vec<Vec3> = vec![];
+    let mut normals: Vec<Vec3> = vec![];
+    let mut uvs: Vec<(f64, f64)> = vec![];
+    let mut materials: HashMap<String, ObjMaterial> = HashMap::new();
+
+    let mut builders: Vec<MeshBuilder> = vec![];
+    let mut builder_lookup: HashMap<(String, Option<String>), usize> = HashMap::new();
+    let mut group = String::from("default");
+    let mut material: Option<String> = None;
+    let mut warnings: Vec<ObjWarning> = vec![];
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let parse_error = |message: String| ObjError::Parse { path: path.to_path_buf(), line: line_number, message };
+
+        let mut tokens = strip_comment(line).split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let arguments: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => positions.push(parse_vec3(&arguments).map_err(parse_error)?),
+            "vn" => normals.push(parse_vec3(&arguments).map_err(parse_error)?),
+            "vt" => {
+                let values = parse_floats(&arguments, 1, 3).map_err(parse_error)?;
+                uvs.push((values[0], values.get(1).copied().unwrap_or(0.)));
+            }
+            "f" => {
+                if arguments.len() < 3 {
+                    return Err(parse_error(format!("face needs at least 3 vertices, found {}", arguments.len())));
+                }
+
+                let face = arguments
+                    .iter()
+                    .map(|vertex| parse_face_vertex(vertex, positions.len(), uvs.len(), normals.len()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(parse_error)?;
+
+                let key = (group.clone(), material.clone());
+                let builder_index = *builder_lookup.entry(key).or_insert_with(|| {
+                    builders.push(MeshBuilder::new(group.clone(), material.clone()));
+                    builders.len() - 1
+                });
+
+                builders[builder_index].add_polygon(&face, &positions, &uvs, &normals);
+            }
+            "g" | "o" => {
+                group = if arguments.is_empty() { String::from("default") } else { arguments.join(" ") };
+            }
+            "usemtl" => {
+                let name = arguments.join(" ");
+                if name.is_empty() {
+                    return Err(parse_error(String::from("usemtl without a material name")));
+                }
+                // Exporters often leave materials out of the library, which
+                // is no reason to lose the geometry
+                if materials.contains_key(&name) {
+                    material = Some(name);
+                } else {
+                    warnings.push(ObjWarning {
+                        path: path.to_path_buf(),
+                        line: line_number,
+                        message: format!("unknown material '{}', using the default material", name),
+                    });
+                    material = None;
+                }
+            }
+            "mtllib" => {
+                if arguments.is_empty() {
+                    return Err(parse_error(String::from("mtllib without a file name")));
+                }
+                let directory = path.parent().unwrap_or_else(|| Path::new(""));
+                for library in arguments {
+                    for obj_material in load_mtl(directory.join(library))? {
+                        materials.insert(obj_material.name.clone(), obj_material);
+                    }
+                }
+            }
+            // Smoothing groups, lines, points and free-form geometry are not rendered
+            _ => {}
+        }
+    }
+
+    // Meshes with the same material share it, so it gets one material id
+    let mut built_materials: HashMap<Option<String>, Arc<dyn Material>> = HashMap::new();
+    let meshes = builders
+        .into_iter()
+        .filter(|builder| !builder.indices.is_empty())
+        .map(|builder| {
+            let material = match &builder.material {
+                Some(name) => materials[name].clone(),
+                None => ObjMaterial::new("default"),
+            };
+            let built_material = built_materials
+                .entry(builder.material.clone())
+                .or_insert_with(|| material.to_material())
+                .clone();
+            builder.build(material, built_material)
+        })
+        .collect();
+
+    Ok(ObjMeshes { meshes, warnings })
+}
+
+pub fn load_mtl<P: AsRef<Path>>(path: P) -> Result<Vec<ObjMaterial>, ObjError> {
+    let path = path.as_ref();
+    let source = read(path)?;
+    let mut materials: Vec<ObjMaterial> = vec![];
+
+    for (line_index, line) in source.lines().enumerate() {
+        let line_number = line_index + 1;
+        let parse_error = |message: String| ObjError::Parse { path: path.to_path_buf(), line: line_number, message };
+
+        let mut tokens = strip_comment(line).split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let arguments: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            if arguments.is_empty() {
+                return Err(parse_error(String::from("newmtl without a material name")));
+            }
+            materials.push(ObjMaterial::new(&arguments.join(" ")));
+            continue;
+        }
+
+        let current = match materials.last_mut() {
+            Some(current) => current,
+            None if matches!(keyword, "Kd" | "Ks" | "Ns" | "Ni" | "d" | "Tr" | "illum") => {
+                return Err(parse_error(format!("'{}' before any newmtl", keyword)));
+            }
+            None => continue,
+        };
+
+        match keyword {
+            "Kd" => current.diffuse = parse_color(&arguments).map_err(parse_error)?,
+            "Ks" => current.specular = parse_color(&arguments).map_err(parse_error)?,
+            "Ns" => current.shininess = parse_scalar(&arguments).map_err(parse_error)?,
+            "Ni" => current.ior = parse_scalar(&arguments).map_err(parse_error)?,
+            "d" => current.dissolve = parse_scalar(&arguments).map_err(parse_error)?,
+            "Tr" => current.dissolve = 1. - parse_scalar(&arguments).map_err(parse_error)?,
+            "illum" => {
+                current.illum = arguments
+                    .first()
+                    .and_then(|value| value.parse().ok())
+                    .ok_or_else(|| parse_error(String::from("illum expects an integer")))?;
+            }
+            // Texture maps and other statements are not supported
+            _ => {}
+        }
+    }
+
+    Ok(materials)
+}
+
+struct MeshBuilder {
+    group: String,
+    material: Option<String>,
+    positions: Vec<Vec3>,
+    normals: Vec<Option<Vec3>>,
+    uvs: Vec<Option<(f64, f64)>>,
+    indices: Vec<[usize; 3]>,
+    // (position, uv, normal) of the file to vertex of the mesh
+    vertex_lookup: HashMap<FaceVertex, usize>,
+}
+
+impl MeshBuilder {
+    fn new(group: String, material: Option<String>) -> MeshBuilder {
+        MeshBuilder {
+            group,
+            material,
+            positions: vec![],
+            normals: vec![],
+            uvs: vec![],
+            indices: vec![],
+            vertex_lookup: HashMap::new(),
+        }
+    }
+
+    fn vertex(&mut self, vertex: FaceVertex, positions: &[Vec3], uvs: &[(f64, f64)], normals: &[Vec3]) -> usize {
+        if let Some(&index) = self.vertex_lookup.get(&vertex) {
+            return index;
+        }
+
+        let index = self.positions.len();
+        self.positions.push(positions[vertex.position]);
+        self.uvs.push(vertex.uv.map(|i| uvs[i]));
+        self.normals.push(vertex.normal.map(|i| normals[i]));
+        self.vertex_lookup.insert(vertex, index);
+        index
+    }
+
+    // Fan triangulation, polygons are expected to be convex
+    fn add_polygon(&mut self, face: &[FaceVertex], positions: &[Vec3], uvs: &[(f64, f64)], normals: &[Vec3]) {
+        let first = self.vertex(face[0], positions, uvs, normals);
+
+        for pair in face[1..].windows(2) {
+            let second = self.vertex(pair[0], positions, uvs, normals);
+            let third = self.vertex(pair[1], positions, uvs, normals);
+            self.indices.push([first, second, third]);
+        }
+    }
+
+    fn build(self, material: ObjMaterial, built_material: Arc<dyn Material>) -> ObjMesh {
+        // Every index is a vertex this builder added
+        let mut mesh = TriangleMesh::new(self.positions, self.indices, built_material)
+            .expect("mesh indices are in range");
+
+        // Attributes are only kept if every vertex of the mesh has them
+        if let Some(normals) = self.normals.into_iter().collect::<Option<Vec<_>>>() {
+            mesh = mesh.with_normals(normals);
+        }
+        if let Some(uvs) = self.uvs.into_iter().collect::<Option<Vec<_>>>() {
+            mesh = mesh.with_uvs(uvs);
+        }
+
+        ObjMesh {
+            group: self.group,
+            material,
+            mesh,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct FaceVertex {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+fn read(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|source| ObjError::Io { path: path.to_path_buf(), source })
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find('#') {
+        Some(index) => &line[..index],
+        None => line,
+    }
+}
+
+fn parse_floats(arguments: &[&str], min: usize, max: usize) -> Result<Vec<f64>, String> {
+    if arguments.len() < min || arguments.len() > max {
+        return Err(format!("expected {} to {} numbers, found {}", min, max, arguments.len()));
+    }
+
+    arguments
+        .iter()
+        .map(|value| value.parse::<f64>().map_err(|_| format!("invalid number '{}'", value)))
+        .collect()
+}
+
+fn parse_scalar(arguments: &[&str]) -> Result<f64, String> {
+    Ok(parse_floats(arguments, 1, 1)?[0])
+}
+
+// Positions may carry an optional w component, which is ignored
+fn parse_vec3(arguments: &[&str]) -> Result<Vec3, String> {
+    let values = parse_floats(arguments, 3, 4)?;
+    Ok(Vec3::new(values[0], values[1], values[2]))
+}
+
+// A single value is a grey
+fn parse_color(arguments: &[&str]) -> Result<Vec3, String> {
+    let values = parse_floats(arguments, 1, 3)?;
+    match values[..] {
+        [grey] => Ok(Vec3::constant_new(grey)),
+        [r, g, b] => Ok(Vec3::new(r, g, b)),
+        _ => Err(format!("expected 1 or 3 color components, found {}", values.len())),
+    }
+}
+
+// Resolves "v", "v/vt", "v//vn" or "v/vt/vn", indices are 1-based and
+// negative ones count back from the latest element
+fn parse_face_vertex(vertex: &str, position_count: usize, uv_count: usize, normal_count: usize) -> Result<FaceVertex, String> {
+    let mut parts = vertex.split('/');
+
+    let resolve = |part: Option<&str>, count: usize, kind: &str| -> Result<Option<usize>, String> {
+        let part = match part {
+            Some(part) if !part.is_empty() => part,
+            _ => return Ok(None),
+        };
+
+        let index: i64 = part.parse().map_err(|_| format!("invalid {} index '{}' in '{}'", kind, part, vertex))?;
+        let resolved = if index > 0 { index - 1 } else { count as i64 + index };
+
+        if index == 0 || resolved < 0 || resolved >= count as i64 {
+            return Err(format!("{} index {} out of range ({} defined)", kind, index, count));
+        }
+
+        Ok(Some(resolved as usize))
+    };
+
+    let position = resolve(parts.next(), position_count, "vertex")?
+        .ok_or_else(|| format!("face vertex '{}' has no position", vertex))?;
+    let uv = resolve(parts.next(), uv_count, "texture coordinate")?;
+    let normal = resolve(parts.next(), normal_count, "normal")?;
+
+    if parts.next().is_some() {
+        return Err(format!("malformed face vertex '{}'", vertex));
+    }
+
+    Ok(FaceVertex { position, uv, normal })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ray::Ray;
+
+    // Writes the files into a directory of their own and returns it
+    fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("raytracer-obj-{}-{}", std::process::id(), test));
+        fs::create_dir_all(&directory).unwrap();
+        for (name, contents) in files {
+            fs::write(directory.join(name), contents).unwrap();
+        }
+        directory
+    }
+
+    fn parse_error_line(result: Result<ObjMeshes, ObjError>) -> usize {
+        match result {
+            Err(ObjError::Parse { line, .. }) => line,
+            Err(error) => panic!("expected a parse error, got {}", error),
+            Ok(_) => panic!("expected a parse error"),
+        }
+    }
+
+    const MATERIALS: &str = "\
+newmtl red
+Kd 0.8 0.1 0.1
+
+newmtl glass # transparent
+Ni 1.45
+d 0.5
+";
+
+    const CUBE_SIDES: &str = "\
+mtllib materials.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 0 0 1
+vt 0 0
+vt 1 0
+vt 1 1
+vn 0 0 1
+g front
+usemtl red
+f 1/1/1 2/2/1 3/3/1 4/3/1
+g back
+usemtl glass
+f -5 -4 -1
+";
+
+    #[test]
+    fn loads_groups_materials_and_polygons() {
+        let directory = write_files("groups", &[("sides.obj", CUBE_SIDES), ("materials.mtl", MATERIALS)]);
+        let ObjMeshes { meshes, warnings } = load_meshes(directory.join("sides.obj")).unwrap();
+
+        assert!(warnings.is_empty());
+        assert_eq!(meshes.len(), 2);
+        assert_eq!(meshes[0].group, "front");
+        assert_eq!(meshes[0].material.name, "red");
+        assert_eq!(meshes[0].material.diffuse.x(), 0.8);
+        // The quad is split into a fan of two triangles
+        assert_eq!(meshes[0].mesh.triangle_count(), 2);
+        assert_eq!(meshes[1].group, "back");
+        assert_eq!(meshes[1].material.ior, 1.45);
+        assert_eq!(meshes[1].material.dissolve, 0.5);
+        assert_eq!(meshes[1].mesh.triangle_count(), 1);
+    }
+
+    #[test]
+    fn groups_with_the_same_material_share_its_material_id() {
+        let obj = format!("{}g top\nusemtl red\nf 2 3 5\n", CUBE_SIDES);
+        let directory = write_files("material_ids", &[("sides.obj", &obj), ("materials.mtl", MATERIALS)]);
+        let (world, _) = load_obj(directory.join("sides.obj")).unwrap();
+
+        let material_id = |origin: Vec3, direction: Vec3| {
+            let hit_record = world.did_hit(&Ray::new(origin, direction), 0.001, f64::INFINITY).expect("the ray hits a face");
+            world.material_id(hit_record.material)
+        };
+        let front = material_id(Vec3::new(0.7, 0.2, -1.), Vec3::new(0., 0., 1.));
+        let back = material_id(Vec3::new(0.2, -1., 0.2), Vec3::new(0., 1., 0.));
+        let top = material_id(Vec3::new(2. / 3., 1. / 3., 2.), Vec3::new(0., 0., -1.));
+
+        assert_ne!(front, 0);
+        assert_ne!(back, 0);
+        assert_ne!(front, back);
+        assert_eq!(front, top);
+    }
+
+    #[test]
+    fn loads_materials() {
+        let directory = write_files("mtl", &[("materials.mtl", MATERIALS)]);
+        let materials = load_mtl(directory.join("materials.mtl")).unwrap();
+
+        let names = materials.iter().map(|material| material.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["red", "glass"]);
+        assert_eq!(materials[1].diffuse.x(), 0.8);
+    }
+
+    #[test]
+    fn unknown_material_falls_back_to_the_default() {
+        let obj = "mtllib materials.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl missing\nf 1 2 3\n";
+        let directory = write_files("unknown_material", &[("mesh.obj", obj), ("materials.mtl", MATERIALS)]);
+        let ObjMeshes { meshes, warnings } = load_meshes(directory.join("mesh.obj")).unwrap();
+
+        assert_eq!(meshes.len(), 1);
+        assert_eq!(meshes[0].material.name, "default");
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].line, 5);
+        assert!(warnings[0].message.contains("'missing'"), "{}", warnings[0]);
+    }
+
+    #[test]
+    fn rejects_bad_obj_files() {
+        let cases = [
+            ("index", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4\n", 4),
+            ("zero_index", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 0 1 2\n", 4),
+            ("two_vertices", "v 0 0 0\nv 1 0 0\nf 1 2\n", 3),
+            ("bad_number", "v 0 0 0\nv 1 zero 0\n", 2),
+            ("bad_face_vertex", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/1/1/1 2 3\n", 4),
+            ("missing_uv", "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1/1 2/1 3/1\n", 4),
+            ("empty_usemtl", "usemtl\n", 1),
+        ];
+
+        for (test, obj, line) in cases {
+            let directory = write_files(test, &[("mesh.obj", obj)]);
+            assert_eq!(parse_error_line(load_meshes(directory.join("mesh.obj"))), line, "{}", test);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_mtl_files() {
+        let cases = [("before_newmtl", "Kd 1 1 1\n", 1), ("bad_color", "newmtl a\nKd 1 1\n", 2), ("bad_illum", "newmtl a\nillum x\n", 2)];
+
+        for (test, mtl, line) in cases {
+            let obj = "mtllib materials.mtl\n";
+            let directory = write_files(test, &[("mesh.obj", obj), ("materials.mtl", mtl)]);
+            assert_eq!(parse_error_line(load_meshes(directory.join("mesh.obj"))), line, "{}", test);
+        }
+    }
+
+    #[test]
+    fn missing_files_are_io_errors() {
+        let directory = write_files("missing", &[("mesh.obj", "mtllib nowhere.mtl\n")]);
+
+        assert!(matches!(load_meshes(directory.join("absent.obj")), Err(ObjError::Io { .. })));
+        assert!(matches!(load_meshes(directory.join("mesh.obj")), Err(ObjError::Io { .. })));
+    }
+}
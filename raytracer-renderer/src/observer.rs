@@ -0,0 +1,119 @@
+use crate::RenderedTile;
+use crate::cancel::RenderStatus;
+
+use std::sync::atomic::{ AtomicU64, AtomicUsize, Ordering };
+use std::time::{ Duration, Instant };
+
+// Least time between two progress events
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+// Where a render is at
+#[derive(Clone, Copy, Debug)]
+pub struct RenderProgress {
+    // Pixels with all the samples they get
+    pub finished_pixels: usize,
+    pub total_pixels: usize,
+    pub samples: u64,
+    // Samples if no pixel stopped early with adaptive sampling
+    pub max_samples: u64,
+    pub elapsed: Duration,
+}
+
+impl RenderProgress {
+    // Fraction of the work done in [0, 1]. Tiled renders finish pixel by
+    // pixel while progressive ones add samples everywhere, whichever is
+    // further along is the better guess.
+    pub fn fraction(&self) -> f64 {
+        let pixels = self.finished_pixels as f64 / self.total_pixels.max(1) as f64;
+        let samples = self.samples as f64 / self.max_samples.max(1) as f64;
+        f64::min(f64::max(pixels, samples), 1.)
+    }
+
+    // Estimated time left, assuming the rest renders as fast as what is done
+    pub fn eta(&self) -> Option<Duration> {
+        let fraction = self.fraction();
+        if fraction <= 0. {
+            return None;
+        }
+
+        Some(self.elapsed.mul_f64((1. - fraction) / fraction))
+    }
+}
+
+// Receives events while a render runs. Events come from the render threads,
+// all methods do nothing by default.
+pub trait RenderObserver: Sync {
+    fn started(&self, _progress: &RenderProgress) {}
+
+    // Tiled renders only, in the order tiles finish
+    fn tile_finished(&self, _tile: &RenderedTile, _progress: &RenderProgress) {}
+
+    // Progressive renders only, pass counts from 1
+    fn pass_finished(&self, _pass: usize, _progress: &RenderProgress) {}
+
+    // At most every PROGRESS_INTERVAL
+    fn progress(&self, _progress: &RenderProgress) {}
+
+    fn completed(&self, _progress: &RenderProgress) {}
+
+    // Instead of completed when the render was cancelled or ran out of time
+    fn stopped(&self, _status: RenderStatus, _progress: &RenderProgress) {}
+}
+
+// For renders nobody watches
+impl RenderObserver for () {}
+
+// Counters shared by the render threads
+pub(crate) struct ProgressTracker<'a> {
+    observer: &'a dyn RenderObserver,
+    start: Instant,
+    total_pixels: usize,
+    max_samples: u64,
+    finished_pixels: AtomicUsize,
+    samples: AtomicU64,
+    // Milliseconds after start of the last progress event
+    last_progress: AtomicU64,
+}
+
+impl<'a> ProgressTracker<'a> {
+    pub(crate) fn new(observer: &'a dyn RenderObserver, total_pixels: usize, samples_per_pixel: usize) -> ProgressTracker<'a> {
+        ProgressTracker {
+            observer,
+            start: Instant::now(),
+            total_pixels,
+            max_samples: (total_pixels * samples_per_pixel) as u64,
+            finished_pixels: AtomicUsize::new(0),
+            samples: AtomicU64::new(0),
+            last_progress: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn observer(&self) -> &'a dyn RenderObserver {
+        self.observer
+    }
+
+    pub(crate) fn progress(&self) -> RenderProgress {
+        RenderProgress {
+            finished_pixels: self.finished_pixels.load(Ordering::Relaxed),
+            total_pixels: self.total_pixels,
+            samples: self.samples.load(Ordering::Relaxed),
+            max_samples: self.max_samples,
+            elapsed: self.start.elapsed(),
+        }
+    }
+
+    // Counts finished work and sends a progress event unless one went out
+    // less than PROGRESS_INTERVAL ago
+    pub(crate) fn add(&self, finished_pixels: usize, samples: u64) {
+        self.finished_pixels.fetch_add(finished_pixels, Ordering::Relaxed);
+        self.samples.fetch_add(samples, Ordering::Relaxed);
+
+        let now = self.start.elapsed().as_millis() as u64;
+        let last = self.last_progress.load(Ordering::Relaxed);
+        // Only the thread that wins the exchange reports
+        if now >= last + PROGRESS_INTERVAL.as_millis() as u64
+            && self.last_progress.compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed).is_ok() {
+            self.observer.progress(&self.progress());
+        }
+    }
+}
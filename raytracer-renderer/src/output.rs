@@ -0,0 +1,368 @@
+use crate::aov::AovBuffer;
+use crate::framebuffer::Framebuffer;
+use crate::tonemap::ToneMapping;
+
+use exr::prelude::{ AnyChannel, AnyChannels, FlatSamples, Image, SmallVec, WritableImage };
+
+use image::{ ColorType, ImageBuffer, ImageError, ImageOutputFormat, Rgb };
+use image::codecs::pnm::{ PnmSubtype, SampleEncoding };
+use image::codecs::webp::WebPEncoder;
+
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{ self, BufWriter, Cursor, Seek, Write };
+use std::path::{ Path, PathBuf };
+
+pub const DEFAULT_JPEG_QUALITY: u8 = 90;
+
+// File formats a render can be written as
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+    Png,
+    // 16 bits per channel, less banding in dark gradients
+    Png16,
+    Jpeg,
+    // Lossless
+    Webp,
+    Bmp,
+    Tga,
+    // Binary 8 bit PPM
+    Ppm,
+    // OpenEXR, linear 32 bit float
+    Exr,
+    // Radiance RGBE, linear
+    Hdr,
+    // Portable float map, linear 32 bit float
+    Pfm,
+}
+
+impl OutputFormat {
+    // Png16 is never picked from the extension, .png means 8 bit
+    pub fn from_extension(extension: &str) -> Option<OutputFormat> {
+        match extension.to_ascii_lowercase().as_str() {
+            "png" => Some(OutputFormat::Png),
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            "webp" => Some(OutputFormat::Webp),
+            "bmp" => Some(OutputFormat::Bmp),
+            "tga" => Some(OutputFormat::Tga),
+            "ppm" | "pnm" => Some(OutputFormat::Ppm),
+            "exr" => Some(OutputFormat::Exr),
+            "hdr" => Some(OutputFormat::Hdr),
+            "pfm" => Some(OutputFormat::Pfm),
+            _ => None,
+        }
+    }
+
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<OutputFormat> {
+        OutputFormat::from_extension(path.as_ref().extension()?.to_str()?)
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            OutputFormat::Png | OutputFormat::Png16 => "image/png",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Webp => "image/webp",
+            OutputFormat::Bmp => "image/bmp",
+            OutputFormat::Tga => "image/x-tga",
+            OutputFormat::Ppm => "image/x-portable-pixmap",
+            OutputFormat::Exr => "image/x-exr",
+            OutputFormat::Hdr => "image/vnd.radiance",
+            OutputFormat::Pfm => "application/octet-stream",
+        }
+    }
+
+    // Float formats keep the linear radiance, the others get it tone mapped
+    pub fn is_linear(self) -> bool {
+        matches!(self, OutputFormat::Exr | OutputFormat::Hdr | OutputFormat::Pfm)
+    }
+}
+
+#[derive(Debug)]
+pub enum OutputError {
+    // The path has no extension that names a format
+    UnknownFormat(PathBuf),
+    Io(io::Error),
+    Image(ImageError),
+    Exr(exr::error::Error),
+}
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OutputError::UnknownFormat(path) => write!(f, "cannot tell the image format of '{}'", path.display()),
+            OutputError::Io(error) => write!(f, "{}", error),
+            OutputError::Image(error) => write!(f, "{}", error),
+            OutputError::Exr(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for OutputError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            OutputError::UnknownFormat(_) => None,
+            OutputError::Io(error) => Some(error),
+            OutputError::Image(error) => Some(error),
+            OutputError::Exr(error) => Some(error),
+        }
+    }
+}
+
+impl From<io::Error> for OutputError {
+    fn from(error: io::Error) -> OutputError {
+        OutputError::Io(error)
+    }
+}
+
+impl From<ImageError> for OutputError {
+    fn from(error: ImageError) -> OutputError {
+        OutputError::Image(error)
+    }
+}
+
+impl From<exr::error::Error> for OutputError {
+    fn from(error: exr::error::Error) -> OutputError {
+        OutputError::Exr(error)
+    }
+}
+
+// How to write a framebuffer to a file or to memory
+#[derive(Clone, Copy, Debug)]
+pub struct ImageOutput {
+    pub format: OutputFormat,
+    // 1 to 100
+    pub jpeg_quality: u8,
+}
+
+impl ImageOutput {
+    pub fn new(format: OutputFormat) -> ImageOutput {
+        ImageOutput { format, jpeg_quality: DEFAULT_JPEG_QUALITY }
+    }
+
+    // Format from the extension of the path
+    pub fn for_path<P: AsRef<Path>>(path: P) -> Result<ImageOutput, OutputError> {
+        let path = path.as_ref();
+        OutputFormat::from_path(path)
+            .map(ImageOutput::new)
+            .ok_or_else(|| OutputError::UnknownFormat(path.to_path_buf()))
+    }
+
+    pub fn with_jpeg_quality(mut self, quality: u8) -> ImageOutput {
+        self.jpeg_quality = quality.clamp(1, 100);
+        self
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, framebuffer: &Framebuffer, tone_mapping: &ToneMapping)
+                                -> Result<(), OutputError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer, framebuffer, tone_mapping)?;
+        Ok(writer.flush()?)
+    }
+
+    // The whole file in memory, for sending it elsewhere
+    pub fn encode(&self, framebuffer: &Framebuffer, tone_mapping: &ToneMapping) -> Result<Vec<u8>, OutputError> {
+        let mut cursor = Cursor::new(Vec::new());
+        self.write(&mut cursor, framebuffer, tone_mapping)?;
+        Ok(cursor.into_inner())
+    }
+
+    pub fn write<W: Write + Seek>(&self, writer: &mut W, framebuffer: &Framebuffer, tone_mapping: &ToneMapping)
+                                  -> Result<(), OutputError> {
+        let (width, height) = (framebuffer.width(), framebuffer.height());
+
+        let image_format = match self.format {
+            OutputFormat::Exr => return Ok(framebuffer.write_exr(writer)?),
+            OutputFormat::Hdr => return Ok(framebuffer.write_hdr(writer)?),
+            OutputFormat::Pfm => return Ok(framebuffer.write_pfm(writer)?),
+            OutputFormat::Png16 => {
+                let image = ImageBuffer::<Rgb<u16>, _>::from_raw(width as u32, height as u32, tone_mapping.to_rgb16(framebuffer))
+                    .expect("three channels per pixel");
+                return Ok(image.write_to(writer, ImageOutputFormat::Png)?);
+            }
+            OutputFormat::Webp => {
+                let buffer = tone_mapping.to_rgb8(framebuffer);
+                return Ok(WebPEncoder::new_lossless(writer).encode(&buffer, width as u32, height as u32, ColorType::Rgb8)?);
+            }
+            OutputFormat::Png => ImageOutputFormat::Png,
+            OutputFormat::Jpeg => ImageOutputFormat::Jpeg(self.jpeg_quality),
+            OutputFormat::Bmp => ImageOutputFormat::Bmp,
+            OutputFormat::Tga => ImageOutputFormat::Tga,
+            OutputFormat::Ppm => ImageOutputFormat::Pnm(PnmSubtype::Pixmap(SampleEncoding::Binary)),
+        };
+
+        let buffer = tone_mapping.to_rgb8(framebuffer);
+        Ok(image::write_buffer_with_format(writer, &buffer, width as u32, height as u32, ColorType::Rgb8, image_format)?)
+    }
+}
+
+pub fn save_exr_layers<P: AsRef<Path>>(path: P, framebuffer: &Framebuffer, aovs: &[AovBuffer]) -> Result<(), OutputError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_exr_layers(&mut writer, framebuffer, aovs)?;
+    Ok(writer.flush()?)
+}
+
+// One OpenEXR file with the color as R, G and B and the AOVs as channels
+// prefixed with their name, e.g. depth.Z, which compositing programs show as
+// layers
+pub fn write_exr_layers<W: Write + Seek>(writer: &mut W, framebuffer: &Framebuffer, aovs: &[AovBuffer])
+                                         -> Result<(), OutputError> {
+    let channel = |name: &str, framebuffer: &Framebuffer, index: usize| {
+        AnyChannel::new(name, FlatSamples::F32(framebuffer.pixels().iter().map(|pixel| pixel[index]).collect()))
+    };
+
+    let mut channels = ["R", "G", "B"]
+        .iter()
+        .enumerate()
+        .map(|(index, name)| channel(name, framebuffer, index))
+        .collect::<Vec<_>>();
+    for buffer in aovs {
+        for (index, name) in buffer.aov.channels().iter().enumerate() {
+            channels.push(channel(&format!("{}.{}", buffer.aov.name(), name), &buffer.framebuffer, index));
+        }
+    }
+
+    let size = (framebuffer.width(), framebuffer.height());
+    let image = Image::from_channels(size, AnyChannels::sort(SmallVec::from_vec(channels)));
+    Ok(image.write().to_buffered(writer)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::aov::Aov;
+    use crate::vec3::Vec3;
+
+    use exr::prelude::{ ReadChannels, ReadLayers };
+    use image::codecs::hdr::HdrDecoder;
+
+    // Linear values far outside of [0, 1], dark ones included
+    fn hdr_framebuffer() -> Framebuffer {
+        let (width, height) = (5, 3);
+        let colors = (0..width * height).map(|i| Vec3::new(1e-4 * (i + 1) as f64, 0.5 + i as f64, 50. / (i + 1) as f64));
+        Framebuffer::from_colors(width, height, colors)
+    }
+
+    // Name and samples of every channel of the first layer
+    fn read_exr_channels(bytes: &[u8]) -> Vec<(String, Vec<f32>)> {
+        let image = exr::prelude::read()
+            .no_deep_data()
+            .largest_resolution_level()
+            .all_channels()
+            .first_valid_layer()
+            .all_attributes()
+            .from_buffered(Cursor::new(bytes))
+            .unwrap();
+
+        image.layer_data.channel_data.list
+            .iter()
+            .map(|channel| match &channel.sample_data {
+                FlatSamples::F32(samples) => (channel.name.to_string(), samples.clone()),
+                _ => panic!("{} is not 32 bit float", channel.name),
+            })
+            .collect()
+    }
+
+    fn encode(format: OutputFormat, framebuffer: &Framebuffer) -> Vec<u8> {
+        ImageOutput::new(format).encode(framebuffer, &ToneMapping::default()).unwrap()
+    }
+
+    #[test]
+    fn exr_keeps_the_linear_values() {
+        let framebuffer = hdr_framebuffer();
+        let channels = read_exr_channels(&encode(OutputFormat::Exr, &framebuffer));
+
+        let names = channels.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["B", "G", "R"]);
+        for (name, samples) in &channels {
+            let index = ["R", "G", "B"].iter().position(|channel| channel == name).unwrap();
+            let expected = framebuffer.pixels().iter().map(|pixel| pixel[index]).collect::<Vec<_>>();
+            assert_eq!(*samples, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn hdr_keeps_the_linear_values_to_its_precision() {
+        let framebuffer = hdr_framebuffer();
+        let hdr = encode(OutputFormat::Hdr, &framebuffer);
+        let decoder = HdrDecoder::new(Cursor::new(hdr)).unwrap();
+        let metadata = decoder.metadata();
+        let decoded = decoder.read_image_hdr().unwrap();
+
+        assert_eq!((metadata.width as usize, metadata.height as usize), (framebuffer.width(), framebuffer.height()));
+        for (decoded, expected) in decoded.iter().zip(framebuffer.pixels()) {
+            // RGBE shares one exponent, the largest channel keeps 8 bits
+            let tolerance = expected.iter().fold(0f32, |max, &value| max.max(value)) / 128.;
+            for channel in 0..3 {
+                assert!((decoded[channel] - expected[channel]).abs() <= tolerance, "{:?} is not {:?}", decoded, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn pfm_stores_little_endian_rows_from_the_bottom() {
+        let framebuffer = hdr_framebuffer();
+        let pfm = encode(OutputFormat::Pfm, &framebuffer);
+
+        let header = format!("PF\n{} {}\n-1.0\n", framebuffer.width(), framebuffer.height());
+        assert!(pfm.starts_with(header.as_bytes()));
+
+        let samples = pfm[header.len()..]
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        let rows = framebuffer.pixels().chunks(framebuffer.width()).rev();
+        let expected = rows.flat_map(|row| row.iter().flatten().copied()).collect::<Vec<_>>();
+        assert_eq!(samples, expected);
+    }
+
+    #[test]
+    fn exr_layers_name_the_channels_after_the_aov() {
+        let framebuffer = hdr_framebuffer();
+        let (width, height) = (framebuffer.width(), framebuffer.height());
+        let depth = Framebuffer::from_colors(width, height, (0..width * height).map(|i| Vec3::new(i as f64, i as f64, i as f64)));
+        let normal = Framebuffer::from_colors(width, height, (0..width * height).map(|i| Vec3::new(-1., 0.5, i as f64 / 10.)));
+        let aovs = [
+            AovBuffer { aov: Aov::Depth, framebuffer: depth.clone() },
+            AovBuffer { aov: Aov::Normal, framebuffer: normal.clone() },
+        ];
+
+        let mut cursor = Cursor::new(Vec::new());
+        write_exr_layers(&mut cursor, &framebuffer, &aovs).unwrap();
+        let channels = read_exr_channels(cursor.get_ref());
+
+        let names = channels.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["B", "G", "R", "depth.Z", "normal.X", "normal.Y", "normal.Z"]);
+
+        let expected = |framebuffer: &Framebuffer, index: usize| {
+            framebuffer.pixels().iter().map(|pixel| pixel[index]).collect::<Vec<_>>()
+        };
+        for (name, samples) in &channels {
+            let expected = match name.as_str() {
+                "R" => expected(&framebuffer, 0),
+                "G" => expected(&framebuffer, 1),
+                "B" => expected(&framebuffer, 2),
+                "depth.Z" => expected(&depth, 0),
+                "normal.X" => expected(&normal, 0),
+                "normal.Y" => expected(&normal, 1),
+                _ => expected(&normal, 2),
+            };
+            assert_eq!(*samples, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn webp_is_lossless() {
+        let (width, height) = (13, 7);
+        let colors = (0..width * height).map(|i| Vec3::new((i % width) as f64 / width as f64, (i / width) as f64 / height as f64, 0.5));
+        let framebuffer = Framebuffer::from_colors(width, height, colors);
+        let tone_mapping = ToneMapping::default();
+
+        let webp = ImageOutput::new(OutputFormat::Webp).encode(&framebuffer, &tone_mapping).unwrap();
+        let decoded = image::load_from_memory_with_format(&webp, image::ImageFormat::WebP).unwrap().to_rgb8();
+
+        assert_eq!((decoded.width(), decoded.height()), (width as u32, height as u32));
+        assert_eq!(decoded.into_raw(), tone_mapping.to_rgb8(&framebuffer));
+    }
+}
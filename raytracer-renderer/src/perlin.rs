@@ -0,0 +1,86 @@
+use crate::vec3::Vec3;
+
+use rand::rngs::StdRng;
+use rand::{ Rng, SeedableRng };
+use rand::seq::SliceRandom;
+
+const POINT_COUNT: usize = 256;
+
+// Gradient noise from Ray Tracing: The Next Week, random unit vectors on a
+// lattice hashed through three permutation tables
+pub struct Perlin {
+    gradients: Vec<Vec3>,
+    permute_x: Vec<usize>,
+    permute_y: Vec<usize>,
+    permute_z: Vec<usize>,
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Perlin {
+        let mut rng = StdRng::seed_from_u64(seed);
+
+        let gradients = (0..POINT_COUNT)
+            .map(|_| Vec3::new(rng.gen_range(-1. ..1.), rng.gen_range(-1. ..1.), rng.gen_range(-1. ..1.)).unit_vector())
+            .collect();
+
+        let permutation = |rng: &mut StdRng| {
+            let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+            p.shuffle(rng);
+            p
+        };
+
+        Perlin {
+            gradients,
+            permute_x: permutation(&mut rng),
+            permute_y: permutation(&mut rng),
+            permute_z: permutation(&mut rng),
+        }
+    }
+
+    // In [-1, 1]
+    pub fn noise(&self, point: Vec3) -> f64 {
+        let (u, v, w) = (point.x() - point.x().floor(), point.y() - point.y().floor(), point.z() - point.z().floor());
+        let (i, j, k) = (point.x().floor() as i64, point.y().floor() as i64, point.z().floor() as i64);
+
+        // Hermite smoothing hides the lattice
+        let (uu, vv, ww) = (u * u * (3. - 2. * u), v * v * (3. - 2. * v), w * w * (3. - 2. * w));
+
+        let mut accumulated = 0.;
+        for di in 0..2 {
+            for dj in 0..2 {
+                for dk in 0..2 {
+                    let gradient = self.gradients[
+                        self.permute_x[((i + di) & 255) as usize] ^
+                        self.permute_y[((j + dj) & 255) as usize] ^
+                        self.permute_z[((k + dk) & 255) as usize]
+                    ];
+
+                    let (fi, fj, fk) = (di as f64, dj as f64, dk as f64);
+                    let weight = Vec3::new(u - fi, v - fj, w - fk);
+
+                    accumulated += (fi * uu + (1. - fi) * (1. - uu))
+                        * (fj * vv + (1. - fj) * (1. - vv))
+                        * (fk * ww + (1. - fk) * (1. - ww))
+                        * Vec3::dot(&gradient, &weight);
+                }
+            }
+        }
+
+        accumulated
+    }
+
+    // Sum of depth octaves of noise with halving weights, in [0, 2)
+    pub fn turbulence(&self, point: Vec3, depth: usize) -> f64 {
+        let mut accumulated = 0.;
+        let mut point = point;
+        let mut weight = 1.;
+
+        for _ in 0..depth {
+            accumulated += weight * self.noise(point);
+            weight *= 0.5;
+            point = point * 2.;
+        }
+
+        accumulated.abs()
+    }
+}
@@ -0,0 +1,67 @@
+use crate::hittable::{ Hittable, HitRecord };
+use crate::vec3::Vec3;
+use crate::ray::Ray;
+use crate::material::Material;
+use crate::aabb::Aabb;
+
+use std::sync::Arc;
+
+// Denominators below this are treated as a ray parallel to the plane
+const PARALLEL_EPSILON: f64 = 1e-12;
+
+// Infinite plane through point. It has no bounding box, so the World tests it
+// against every ray outside the BVH.
+pub struct Plane {
+    point: Vec3,
+    normal: Vec3,
+    // Directions in the plane used for the uv coordinates
+    tangent: Vec3,
+    bitangent: Vec3,
+    material: Arc<dyn Material>,
+}
+
+impl Plane {
+    pub fn new(point: Vec3, normal: Vec3, material: Arc<dyn Material>) -> Plane {
+        let normal = normal.unit_vector();
+        // Any axis that is not close to the normal will do
+        let helper = if normal.x().abs() > 0.9 { Vec3::new(0., 1., 0.) } else { Vec3::new(1., 0., 0.) };
+        let tangent = Vec3::cross(&helper, &normal).unit_vector();
+        let bitangent = Vec3::cross(&normal, &tangent);
+
+        Plane {
+            point,
+            normal,
+            tangent,
+            bitangent,
+            material,
+        }
+    }
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let denominator = Vec3::dot(&self.normal, &ray.direction());
+        if denominator.abs() < PARALLEL_EPSILON {
+            return None;
+        }
+
+        let t = Vec3::dot(&(self.point - ray.origin()), &self.normal) / denominator;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+
+        let point = ray.at(t);
+        let front_face = denominator < 0.;
+        let normal = if front_face { self.normal } else { -self.normal };
+
+        // Distances along the plane, so textures repeat every unit
+        let offset = point - self.point;
+        let (u, v) = (Vec3::dot(&offset, &self.tangent), Vec3::dot(&offset, &self.bitangent));
+
+        Some(HitRecord::new(point, normal, &*self.material, t, front_face).with_uv(u, v))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+}
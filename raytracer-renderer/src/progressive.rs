@@ -0,0 +1,245 @@
+use crate::adaptive::PixelStatistics;
+use crate::aov::AovPixel;
+use crate::camera::Camera;
+use crate::settings::RenderSettings;
+use crate::world::World;
+use crate::observer::{ RenderObserver, ProgressTracker };
+use crate::cancel::{ CancellationToken, RenderStatus, StopCheck };
+use crate::framebuffer::Framebuffer;
+use crate::filter::Film;
+use crate::{ RenderOutput, is_pixel_done, render_sample };
+
+use rayon::prelude::*;
+
+// Renders in passes of one sample per pixel, so there is an image of the whole
+// frame after every pass that gets less noisy with each one. Samples are the
+// same as with raytrace_output, so the finished image is too. The time limit of
+// the settings counts from new.
+pub struct ProgressiveRenderer<'a> {
+    settings: &'a RenderSettings,
+    world: &'a World<'a>,
+    camera: &'a Camera,
+    // Running mean of every pixel in linear color
+    accumulation: Vec<PixelStatistics>,
+    // Empty unless the settings ask for AOVs or denoising
+    aov_pixels: Vec<AovPixel>,
+    // Weighted sums of the samples around every pixel, unless each pixel is
+    // the mean of its own
+    film: Option<Film>,
+    passes: usize,
+    tracker: ProgressTracker<'a>,
+    stop: StopCheck,
+}
+
+impl<'a> ProgressiveRenderer<'a> {
+    pub fn new(settings: &'a RenderSettings, world: &'a World<'a>, camera: &'a Camera) -> ProgressiveRenderer<'a> {
+        ProgressiveRenderer {
+            settings,
+            world,
+            camera,
+            accumulation: vec![PixelStatistics::default(); settings.image_width * settings.image_height],
+            aov_pixels: if settings.rendered_aovs().is_empty() {
+                vec![]
+            } else {
+                vec![AovPixel::default(); settings.image_width * settings.image_height]
+            },
+            film: (!settings.filter.is_pixel_mean())
+                .then(|| Film::new(settings.image_width, settings.image_height, settings.filter)),
+            passes: 0,
+            tracker: ProgressTracker::new(&(), settings.image_width * settings.image_height, settings.samples_per_pixel),
+            stop: StopCheck::new(CancellationToken::new(), settings.time_limit),
+        }
+    }
+
+    pub fn with_observer(mut self, observer: &'a dyn RenderObserver) -> ProgressiveRenderer<'a> {
+        self.tracker = ProgressTracker::new(observer, self.accumulation.len(), self.settings.samples_per_pixel);
+        self
+    }
+
+    pub fn with_cancellation(mut self, cancel: &CancellationToken) -> ProgressiveRenderer<'a> {
+        self.stop = StopCheck::new(cancel.clone(), self.settings.time_limit);
+        self
+    }
+
+    // Adds a sample to every pixel that is not done yet, false once there was
+    // nothing left to render or the render stopped during the pass
+    pub fn render_pass(&mut self) -> bool {
+        if self.is_finished() || self.status() != RenderStatus::Completed {
+            return false;
+        }
+
+        let observer = self.tracker.observer();
+        if self.passes == 0 {
+            observer.started(&self.tracker.progress());
+        }
+
+        let ProgressiveRenderer { settings, world, camera, ref film, ref tracker, ref stop, .. } = *self;
+        let add_sample = |i: usize, statistics: &mut PixelStatistics, aovs: Option<&mut AovPixel>| {
+            if is_pixel_done(settings, statistics) || stop.should_stop() {
+                return;
+            }
+
+            let mut sampler = settings.sampler.sampler(settings.seed, settings.samples_per_pixel);
+            let sample = statistics.count();
+            match render_sample(settings, world, camera, &mut *sampler, i, sample, aovs, film.as_ref()) {
+                Some(color) => statistics.add(color),
+                None => statistics.skip(),
+            }
+            tracker.add(is_pixel_done(settings, statistics) as usize, 1);
+        };
+
+        // Zipping with the empty AOVs would skip every pixel
+        if self.aov_pixels.is_empty() {
+            self.accumulation
+                .par_iter_mut()
+                .enumerate()
+                .for_each(|(i, statistics)| add_sample(i, statistics, None));
+        } else {
+            self.accumulation
+                .par_iter_mut()
+                .zip(self.aov_pixels.par_iter_mut())
+                .enumerate()
+                .for_each(|(i, (statistics, aovs))| add_sample(i, statistics, Some(aovs)));
+        }
+
+        // A pass cut short still added samples, but does not count
+        let status = self.status();
+        if status != RenderStatus::Completed {
+            observer.stopped(status, &self.tracker.progress());
+            return false;
+        }
+
+        self.passes += 1;
+        observer.pass_finished(self.passes, &self.tracker.progress());
+        if self.is_finished() {
+            observer.completed(&self.tracker.progress());
+        }
+
+        true
+    }
+
+    pub fn passes(&self) -> usize {
+        self.passes
+    }
+
+    // Completed as long as the render was not stopped, even before it is
+    // finished
+    pub fn status(&self) -> RenderStatus {
+        self.stop.status()
+    }
+
+    // Whether every pixel has all the samples it gets
+    pub fn is_finished(&self) -> bool {
+        self.accumulation.iter().all(|statistics| is_pixel_done(self.settings, statistics))
+    }
+
+    // Linear image of the samples so far, black before the first pass.
+    // Denoised every time if the settings say so.
+    pub fn framebuffer(&self) -> Framebuffer {
+        self.output().framebuffer
+    }
+
+    // Same tone mapped for viewing
+    pub fn image(&self) -> Vec<u8> {
+        self.settings.tone_mapping.to_rgb8(&self.framebuffer())
+    }
+
+    // Renders the remaining passes
+    pub fn finish(mut self) -> RenderOutput {
+        while self.render_pass() {}
+        self.output()
+    }
+
+    // What was rendered so far, stopping need not wait for the last pass
+    pub fn output(&self) -> RenderOutput {
+        RenderOutput::from_pixels(&self.settings.rendered_aovs(), self.settings.image_width, self.settings.image_height,
+                                  &self.accumulation, &self.aov_pixels, self.status())
+            .finished(self.settings, self.film.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::adaptive::AdaptiveSampling;
+    use crate::filter::{ FilterKind, PixelFilter };
+    use crate::raytrace_output;
+    use crate::scene::random_scene_description;
+    use crate::vec3::Vec3;
+
+    use std::path::Path;
+
+    fn scene() -> (World<'static>, Camera) {
+        let mut world = random_scene_description(2, 3)
+            .build_world(Path::new(""))
+            .expect("the random scene description is valid");
+        world.build_bvh();
+        let camera = Camera::new(Vec3::new(13., 2., 3.), Vec3::new(0., 0., 0.), Vec3::new(0., 1., 0.), 20., 1.5, 0.1, 10.);
+        (world, camera)
+    }
+
+    fn settings() -> RenderSettings {
+        let mut settings = RenderSettings::new(24, 16, 6, 8);
+        settings.seed = 11;
+        settings
+    }
+
+    #[test]
+    fn passes_add_up_to_the_tiled_render() {
+        let (world, camera) = scene();
+        let mut adaptive = settings();
+        adaptive.samples_per_pixel = 12;
+        adaptive.adaptive = Some(AdaptiveSampling { noise_threshold: 0.1, min_samples: 4 });
+        let mut filtered = settings();
+        filtered.filter = PixelFilter::new(FilterKind::Mitchell);
+
+        for settings in [settings(), adaptive, filtered] {
+            let expected = raytrace_output(&settings, &world, &camera, &(), &CancellationToken::new());
+
+            let mut renderer = ProgressiveRenderer::new(&settings, &world, &camera);
+            while renderer.render_pass() {}
+            let output = renderer.output();
+
+            assert!(renderer.is_finished());
+            assert_eq!(output.status, RenderStatus::Completed);
+            assert_eq!(output.sample_counts, expected.sample_counts);
+            assert_eq!(output.framebuffer.pixels(), expected.framebuffer.pixels());
+        }
+    }
+
+    #[test]
+    fn finish_renders_every_sample() {
+        let (world, camera) = scene();
+        let settings = settings();
+
+        let output = ProgressiveRenderer::new(&settings, &world, &camera).finish();
+
+        assert_eq!(output.status, RenderStatus::Completed);
+        assert!(output.sample_counts.iter().all(|&count| count == settings.samples_per_pixel));
+        assert!(output.framebuffer.pixels().iter().all(|pixel| pixel.iter().all(|value| value.is_finite())));
+        assert!(output.framebuffer.pixels().iter().any(|pixel| pixel.iter().any(|&value| value > 0.)));
+    }
+
+    #[test]
+    fn stopped_renders_keep_the_passes_so_far() {
+        let (world, camera) = scene();
+        let settings = settings();
+        let cancel = CancellationToken::new();
+
+        let mut renderer = ProgressiveRenderer::new(&settings, &world, &camera).with_cancellation(&cancel);
+        assert!(renderer.render_pass());
+        assert!(renderer.render_pass());
+        let before = renderer.framebuffer();
+
+        cancel.cancel();
+        assert!(!renderer.render_pass());
+        let output = renderer.output();
+
+        assert_eq!(renderer.passes(), 2);
+        assert_eq!(output.status, RenderStatus::Cancelled);
+        assert!(output.sample_counts.iter().all(|&count| count == 2));
+        assert_eq!(output.framebuffer.pixels(), before.pixels());
+        assert!(output.framebuffer.pixels().iter().any(|pixel| pixel.iter().any(|&value| value > 0.)));
+    }
+}
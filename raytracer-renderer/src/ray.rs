@@ -3,11 +3,19 @@ use crate::vec3::Vec3;
 pub struct Ray {
     origin: Vec3,
     direction: Vec3,
+    // Moment within the camera shutter, moving objects are hit where they
+    // are at this time
+    time: f64,
 }
 
 impl Ray {
     pub fn new(origin: Vec3, direction: Vec3) -> Self {
-        Self { origin, direction }
+        Self { origin, direction, time: 0. }
+    }
+
+    pub fn with_time(mut self, time: f64) -> Self {
+        self.time = time;
+        self
     }
 
     pub fn origin(&self) -> Vec3 {
@@ -18,6 +26,10 @@ impl Ray {
         self.direction
     }
 
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
     pub fn at(&self, t: f64) -> Vec3 {
         self.origin + self.direction * t
     }
@@ -0,0 +1,149 @@
+use crate::hittable::{ Hittable, HitRecord };
+use crate::vec3::Vec3;
+use crate::ray::Ray;
+use crate::material::Material;
+use crate::aabb::Aabb;
+
+use std::sync::Arc;
+
+// Rectangles are flat, their bounding boxes get this thickness so the slab
+// test still has something to hit
+const RECT_THICKNESS: f64 = 1e-4;
+
+// Rectangle perpendicular to one of the axes. It spans a0..a1 and b0..b1 in
+// the other two axes, the normal points along the positive third axis.
+pub struct AxisRect {
+    a_axis: usize,
+    b_axis: usize,
+    k_axis: usize,
+    a0: f64,
+    a1: f64,
+    b0: f64,
+    b1: f64,
+    k: f64,
+    material: Arc<dyn Material>,
+}
+
+impl AxisRect {
+    // At z = k
+    pub fn xy(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, material: Arc<dyn Material>) -> AxisRect {
+        AxisRect::new([0, 1, 2], (x0, x1), (y0, y1), k, material)
+    }
+
+    // At y = k
+    pub fn xz(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, material: Arc<dyn Material>) -> AxisRect {
+        AxisRect::new([0, 2, 1], (x0, x1), (z0, z1), k, material)
+    }
+
+    // At x = k
+    pub fn yz(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, material: Arc<dyn Material>) -> AxisRect {
+        AxisRect::new([1, 2, 0], (y0, y1), (z0, z1), k, material)
+    }
+
+    fn new([a_axis, b_axis, k_axis]: [usize; 3], (a0, a1): (f64, f64), (b0, b1): (f64, f64), k: f64,
+           material: Arc<dyn Material>) -> AxisRect {
+        AxisRect {
+            a_axis,
+            b_axis,
+            k_axis,
+            a0: f64::min(a0, a1),
+            a1: f64::max(a0, a1),
+            b0: f64::min(b0, b1),
+            b1: f64::max(b0, b1),
+            k,
+            material,
+        }
+    }
+}
+
+impl Hittable for AxisRect {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let t = (self.k - ray.origin()[self.k_axis]) / ray.direction()[self.k_axis];
+        // Also rejects NaN from rays parallel to the rectangle
+        if !(t > t_min && t < t_max) {
+            return None;
+        }
+
+        let point = ray.at(t);
+        let (a, b) = (point[self.a_axis], point[self.b_axis]);
+        if a < self.a0 || a > self.a1 || b < self.b0 || b > self.b1 {
+            return None;
+        }
+
+        let outward_normal = axis_vector(self.k_axis);
+        let front_face = Vec3::dot(&ray.direction(), &outward_normal) < 0.;
+        let normal = if front_face { outward_normal } else { -outward_normal };
+
+        let u = (a - self.a0) / (self.a1 - self.a0);
+        let v = (b - self.b0) / (self.b1 - self.b0);
+
+        Some(HitRecord::new(point, normal, &*self.material, t, front_face).with_uv(u, v))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut minimum = [0.; 3];
+        let mut maximum = [0.; 3];
+
+        minimum[self.a_axis] = self.a0;
+        maximum[self.a_axis] = self.a1;
+        minimum[self.b_axis] = self.b0;
+        maximum[self.b_axis] = self.b1;
+        minimum[self.k_axis] = self.k - RECT_THICKNESS;
+        maximum[self.k_axis] = self.k + RECT_THICKNESS;
+
+        Some(Aabb::new(Vec3::new(minimum[0], minimum[1], minimum[2]), Vec3::new(maximum[0], maximum[1], maximum[2])))
+    }
+}
+
+// Unit vector along axis
+fn axis_vector(axis: usize) -> Vec3 {
+    let mut components = [0.; 3];
+    components[axis] = 1.;
+    Vec3::new(components[0], components[1], components[2])
+}
+
+// Axis-aligned box made of six rectangles sharing one material
+pub struct BoxShape {
+    minimum: Vec3,
+    maximum: Vec3,
+    sides: [AxisRect; 6],
+}
+
+impl BoxShape {
+    pub fn new(corner: Vec3, opposite_corner: Vec3, material: Arc<dyn Material>) -> BoxShape {
+        let (p0, p1) = (Vec3::min(&corner, &opposite_corner), Vec3::max(&corner, &opposite_corner));
+
+        BoxShape {
+            minimum: p0,
+            maximum: p1,
+            sides: [
+                AxisRect::xy(p0.x(), p1.x(), p0.y(), p1.y(), p0.z(), material.clone()),
+                AxisRect::xy(p0.x(), p1.x(), p0.y(), p1.y(), p1.z(), material.clone()),
+                AxisRect::xz(p0.x(), p1.x(), p0.z(), p1.z(), p0.y(), material.clone()),
+                AxisRect::xz(p0.x(), p1.x(), p0.z(), p1.z(), p1.y(), material.clone()),
+                AxisRect::yz(p0.y(), p1.y(), p0.z(), p1.z(), p0.x(), material.clone()),
+                AxisRect::yz(p0.y(), p1.y(), p0.z(), p1.z(), p1.x(), material),
+            ],
+        }
+    }
+}
+
+impl Hittable for BoxShape {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut closest = None;
+        let mut closest_so_far = t_max;
+
+        for side in &self.sides {
+            if let Some(hit_record) = side.hit(ray, t_min, closest_so_far) {
+                closest_so_far = hit_record.t();
+                closest = Some(hit_record);
+            }
+        }
+
+        closest
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.minimum, self.maximum))
+    }
+}
@@ -0,0 +1,60 @@
+// Small seedable generator for sampling. Every sample gets its own generator
+// derived from the render seed and its pixel and sample index, so images do
+// not depend on which thread rendered what.
+
+// PCG-XSH-RR with 64 bits of state, see https://www.pcg-random.org
+pub struct Pcg32 {
+    state: u64,
+    increment: u64,
+}
+
+const MULTIPLIER: u64 = 6364136223846793005;
+
+impl Pcg32 {
+    // Generators with different streams give unrelated sequences for the same
+    // seed
+    pub fn new(seed: u64, stream: u64) -> Pcg32 {
+        let mut rng = Pcg32 {
+            state: 0,
+            increment: (stream << 1) | 1,
+        };
+        rng.next_u32();
+        rng.state = rng.state.wrapping_add(seed);
+        rng.next_u32();
+        rng
+    }
+
+    pub fn for_sample(seed: u64, pixel_index: usize, sample_index: usize) -> Pcg32 {
+        Pcg32::new(splitmix64(seed ^ splitmix64(pixel_index as u64)), sample_index as u64)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let old = self.state;
+        self.state = old.wrapping_mul(MULTIPLIER).wrapping_add(self.increment);
+
+        let xorshifted = (((old >> 18) ^ old) >> 27) as u32;
+        let rotation = (old >> 59) as u32;
+        xorshifted.rotate_right(rotation)
+    }
+
+    // In [0, 1) with 53 random bits
+    pub fn next_f64(&mut self) -> f64 {
+        let bits = ((self.next_u32() as u64) << 32 | self.next_u32() as u64) >> 11;
+        bits as f64 * (1. / (1u64 << 53) as f64)
+    }
+}
+
+// Mixes all bits of x into all bits of the result
+pub fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+// Number in [0, 1) that is a fixed function of values, for code that has to
+// be random but has no generator at hand
+pub fn hash_to_unit(values: &[f64]) -> f64 {
+    let hash = values.iter().fold(0, |hash: u64, value| splitmix64(hash ^ value.to_bits()));
+    (hash >> 11) as f64 * (1. / (1u64 << 53) as f64)
+}
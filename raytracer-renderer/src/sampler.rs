@@ -0,0 +1,420 @@
+use crate::rng::{ Pcg32, splitmix64 };
+
+use serde::{ Serialize, Deserialize };
+
+// Source of the sample values of one camera path. Every call to get_1d or
+// get_2d takes the next dimension, so consumers have to ask for values in the
+// same order for every sample (pixel position, lens, shutter time, then one
+// or two dimensions per bounce) for the patterns to line up.
+pub trait Sampler {
+    // Restarts at the first dimension of the given sample
+    fn start_sample(&mut self, pixel_index: usize, sample_index: usize);
+
+    // In [0, 1)
+    fn get_1d(&mut self) -> f64;
+
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerKind {
+    // Every value independently random
+    #[default]
+    Independent,
+    // Jittered grid over the samples of a pixel
+    Stratified,
+    // Randomized Halton sequence
+    Halton,
+    // Owen-scrambled Sobol sequence
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn sampler(self, seed: u64, samples_per_pixel: usize) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(seed, samples_per_pixel)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+// Samples are seeded from the render seed, pixel and sample, so the values
+// never depend on the order samples are taken in
+pub struct IndependentSampler {
+    seed: u64,
+    rng: Pcg32,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> IndependentSampler {
+        IndependentSampler {
+            seed,
+            rng: Pcg32::new(seed, 0),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_sample(&mut self, pixel_index: usize, sample_index: usize) {
+        self.rng = Pcg32::for_sample(self.seed, pixel_index, sample_index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.rng.next_f64()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.rng.next_f64(), self.rng.next_f64())
+    }
+}
+
+// Each dimension (pair) splits [0, 1) into one stratum (cell) per sample of the
+// pixel and puts every sample in a different one, jittered inside it. Which
+// sample gets which stratum is shuffled per pixel and dimension so the
+// dimensions do not correlate. Pairs are correlated multi-jittered, from
+// Kensler, "Correlated Multi-Jittered Sampling" (2013): besides their cell of
+// the grid they also fall in different strips along each axis, which keeps
+// them stratified when the grid is a single row for a prime sample count.
+pub struct StratifiedSampler {
+    seed: u64,
+    samples_per_pixel: usize,
+    // Grid for 2D samples, columns * rows == samples_per_pixel
+    columns: usize,
+    rows: usize,
+    pixel_index: usize,
+    sample_index: usize,
+    dimension: u64,
+    rng: Pcg32,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples_per_pixel: usize) -> StratifiedSampler {
+        let samples_per_pixel = samples_per_pixel.max(1);
+        // The closest factor pair, a grid with empty cells leaves part of the
+        // square unsampled
+        let columns = (1..=(samples_per_pixel as f64).sqrt() as usize)
+            .rev()
+            .find(|&columns| samples_per_pixel.is_multiple_of(columns))
+            .unwrap_or(1);
+        let rows = samples_per_pixel / columns;
+
+        StratifiedSampler {
+            seed,
+            samples_per_pixel,
+            columns,
+            rows,
+            pixel_index: 0,
+            sample_index: 0,
+            dimension: 0,
+            rng: Pcg32::new(seed, 0),
+        }
+    }
+
+    // Stratum of the current sample in the next dimension, and the seed of its
+    // shuffle
+    fn next_stratum(&mut self) -> (usize, u32) {
+        let shuffle_seed = dimension_seed(self.seed, self.pixel_index, self.dimension) as u32;
+        self.dimension += 1;
+
+        let stratum = permute(self.sample_index as u32 % self.samples_per_pixel as u32, self.samples_per_pixel as u32,
+                              shuffle_seed);
+        (stratum as usize, shuffle_seed)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_sample(&mut self, pixel_index: usize, sample_index: usize) {
+        self.pixel_index = pixel_index;
+        self.sample_index = sample_index;
+        self.dimension = 0;
+        self.rng = Pcg32::for_sample(self.seed, pixel_index, sample_index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let (stratum, _) = self.next_stratum();
+        (stratum as f64 + self.rng.next_f64()) / self.samples_per_pixel as f64
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (stratum, shuffle_seed) = self.next_stratum();
+        let (columns, rows) = (self.columns as u32, self.rows as u32);
+        let (column, row) = (stratum as u32 % columns, stratum as u32 / columns);
+
+        // Strip inside the cell, the same for all cells of a row (column) so
+        // no two samples share one
+        let strip_x = permute(row, rows, shuffle_seed.wrapping_mul(0xa511e9b3));
+        let strip_y = permute(column, columns, shuffle_seed.wrapping_mul(0x63d83595));
+
+        ((column as f64 + (strip_x as f64 + self.rng.next_f64()) / rows as f64) / columns as f64,
+         (row as f64 + (strip_y as f64 + self.rng.next_f64()) / columns as f64) / rows as f64)
+    }
+}
+
+const PRIMES: [u64; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+// Halton sequence over the samples of a pixel, one prime base per dimension.
+// The digits are shuffled per pixel and dimension (random digit scrambling),
+// which also breaks up the clumping of the first points in large bases.
+// Dimensions past the last prime are independent random values.
+pub struct HaltonSampler {
+    seed: u64,
+    pixel_index: usize,
+    sample_index: usize,
+    dimension: usize,
+    rng: Pcg32,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> HaltonSampler {
+        HaltonSampler {
+            seed,
+            pixel_index: 0,
+            sample_index: 0,
+            dimension: 0,
+            rng: Pcg32::new(seed, 0),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_sample(&mut self, pixel_index: usize, sample_index: usize) {
+        self.pixel_index = pixel_index;
+        self.sample_index = sample_index;
+        self.dimension = 0;
+        self.rng = Pcg32::for_sample(self.seed, pixel_index, sample_index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+
+        match PRIMES.get(dimension) {
+            Some(&base) => {
+                let scramble_seed = dimension_seed(self.seed, self.pixel_index, dimension as u64);
+                scrambled_radical_inverse(self.sample_index as u64, base, scramble_seed)
+            }
+            None => self.rng.next_f64(),
+        }
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// Digits below this weight are left out
+const DIGIT_PRECISION: f64 = 1e-10;
+
+// Index with its base digits mirrored around the point, each digit position
+// shuffled by its own permutation
+fn scrambled_radical_inverse(mut index: u64, base: u64, seed: u64) -> f64 {
+    let inverse_base = 1. / base as f64;
+    let mut weight = inverse_base;
+    let mut value = 0.;
+    let mut position = 0;
+
+    // The zeros past the last digit of index get scrambled as well
+    while index > 0 || weight > DIGIT_PRECISION {
+        let digit_seed = splitmix64(seed ^ position) as u32;
+        let digit = permute((index % base) as u32, base as u32, digit_seed);
+
+        value += digit as f64 * weight;
+        weight *= inverse_base;
+        index /= base;
+        position += 1;
+    }
+
+    // Guard against rounding up to 1
+    f64::min(value, 1. - f64::EPSILON / 2.)
+}
+
+// First two dimensions of the Sobol sequence with hash-based Owen scrambling,
+// from Burley, "Practical Hash-based Owen Scrambling" (2020). Each dimension
+// (pair) gets its own scramble and its own shuffle of the sample order, which
+// needs no direction numbers for higher dimensions.
+pub struct SobolSampler {
+    seed: u64,
+    pixel_index: usize,
+    sample_index: usize,
+    dimension: u64,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> SobolSampler {
+        SobolSampler {
+            seed,
+            pixel_index: 0,
+            sample_index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn next_dimension_seed(&mut self) -> u32 {
+        let seed = dimension_seed(self.seed, self.pixel_index, self.dimension);
+        self.dimension += 1;
+        seed as u32
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_sample(&mut self, pixel_index: usize, sample_index: usize) {
+        self.pixel_index = pixel_index;
+        self.sample_index = sample_index;
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let seed = self.next_dimension_seed();
+        let index = nested_uniform_scramble(self.sample_index as u32, seed);
+        unit_from_u32(nested_uniform_scramble(sobol_0(index), hash_combine(seed, 0)))
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let seed = self.next_dimension_seed();
+        let index = nested_uniform_scramble(self.sample_index as u32, seed);
+
+        (unit_from_u32(nested_uniform_scramble(sobol_0(index), hash_combine(seed, 0))),
+         unit_from_u32(nested_uniform_scramble(sobol_1(index), hash_combine(seed, 1))))
+    }
+}
+
+// The first Sobol dimension is the van der Corput sequence
+fn sobol_0(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+// Second dimension, from the primitive polynomial x + 1
+fn sobol_1(mut index: u32) -> u32 {
+    let mut direction = 1 << 31;
+    let mut value = 0;
+
+    while index != 0 {
+        if index & 1 != 0 {
+            value ^= direction;
+        }
+        index >>= 1;
+        direction ^= direction >> 1;
+    }
+
+    value
+}
+
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+fn hash_combine(seed: u32, value: u32) -> u32 {
+    seed ^ (value.wrapping_add(seed << 6).wrapping_add(seed >> 2))
+}
+
+// Random permutation of 0..length picked by seed, from Kensler,
+// "Correlated Multi-Jittered Sampling" (2013)
+fn permute(mut i: u32, length: u32, seed: u32) -> u32 {
+    let mut mask = length - 1;
+    mask |= mask >> 1;
+    mask |= mask >> 2;
+    mask |= mask >> 4;
+    mask |= mask >> 8;
+    mask |= mask >> 16;
+
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= seed >> 16;
+        i ^= (i & mask) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= seed >> 23;
+        i ^= (i & mask) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & mask) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & mask) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= mask;
+        i ^= i >> 5;
+
+        if i < length {
+            return (i.wrapping_add(seed)) % length;
+        }
+    }
+}
+
+fn dimension_seed(seed: u64, pixel_index: usize, dimension: u64) -> u64 {
+    splitmix64(splitmix64(seed ^ splitmix64(pixel_index as u64)) ^ dimension)
+}
+
+fn unit_from_u32(bits: u32) -> f64 {
+    bits as f64 * (1. / (1u64 << 32) as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Strata of value along an axis split into count strips
+    fn strip(value: f64, count: usize) -> usize {
+        assert!((0. ..1.).contains(&value), "{} is outside [0, 1)", value);
+        (value * count as f64) as usize
+    }
+
+    #[test]
+    fn grid_is_a_factor_pair_of_the_sample_count() {
+        for (samples, columns, rows) in [(1, 1, 1), (2, 1, 2), (6, 2, 3), (7, 1, 7), (12, 3, 4), (16, 4, 4), (18, 3, 6)] {
+            let sampler = StratifiedSampler::new(0, samples);
+            assert_eq!((sampler.columns, sampler.rows), (columns, rows), "{} samples", samples);
+        }
+    }
+
+    #[test]
+    fn every_stratum_gets_one_sample() {
+        for samples in [1, 2, 5, 6, 7, 12, 16, 30] {
+            let mut sampler = StratifiedSampler::new(3, samples);
+
+            for pixel_index in [0, 1, 517] {
+                let mut values_1d = Vec::new();
+                let mut values_2d = Vec::new();
+                for sample_index in 0..samples {
+                    sampler.start_sample(pixel_index, sample_index);
+                    values_1d.push([sampler.get_1d(), sampler.get_1d()]);
+                    values_2d.push([sampler.get_2d(), sampler.get_2d()]);
+                }
+
+                for dimension in 0..2 {
+                    let mut strata: Vec<usize> = values_1d.iter().map(|values| strip(values[dimension], samples)).collect();
+                    strata.sort();
+                    assert_eq!(strata, (0..samples).collect::<Vec<_>>(), "1D, {} samples", samples);
+
+                    let (columns, rows) = (sampler.columns, sampler.rows);
+                    let mut cells: Vec<usize> = values_2d.iter()
+                        .map(|values| strip(values[dimension].1, rows) * columns + strip(values[dimension].0, columns))
+                        .collect();
+                    let mut strips_x: Vec<usize> = values_2d.iter().map(|values| strip(values[dimension].0, samples)).collect();
+                    let mut strips_y: Vec<usize> = values_2d.iter().map(|values| strip(values[dimension].1, samples)).collect();
+                    for strata in [&mut cells, &mut strips_x, &mut strips_y] {
+                        strata.sort();
+                        assert_eq!(*strata, (0..samples).collect::<Vec<_>>(), "2D, {} samples", samples);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,1203 @@
+// Checks are written as !(value > limit) so that NaN, which fails every
+// comparison, is rejected too
+#![allow(clippy::neg_cmp_op_on_partial_ord)]
+
+use crate::camera::Camera;
+use crate::background::{ Background, EnvironmentMap };
+use crate::material::{ Material, Lambertian, Metal, Dielectric, DiffuseLight, Isotropic };
+use crate::obj::{ load_meshes, ObjError, ObjMeshes, ObjWarning };
+use crate::settings::RenderSettings;
+use crate::sampler::SamplerKind;
+use crate::adaptive::AdaptiveSampling;
+use crate::tile::TileOrder;
+use crate::tonemap::ToneMapping;
+use crate::aov::Aov;
+use crate::denoise::Denoising;
+use crate::filter::PixelFilter;
+use crate::sphere::{ Sphere, MovingSphere };
+use crate::plane::Plane;
+use crate::rect::{ AxisRect, BoxShape };
+use crate::instance::{ Instance, AnimatedInstance };
+use crate::animation::{ Keyframe, KeyframedTransform };
+use crate::medium::ConstantMedium;
+use crate::hittable::Hittable;
+use crate::transform::Transform;
+use crate::texture::{ Texture, SolidColor, Checker, ImageTexture, NoiseTexture, NoiseKind, WrapMode, FilterMode };
+use crate::triangle::Triangle;
+use crate::vec3::Vec3;
+use crate::world::World;
+
+use rand::rngs::StdRng;
+use rand::{ Rng, SeedableRng };
+use serde::{ Serialize, Deserialize };
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{ Path, PathBuf };
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io { path: PathBuf, source: io::Error },
+    // Syntax errors and values of the wrong type, key is the path to the value
+    Parse { key: String, message: String },
+    // Values that parse but make no sense
+    Invalid { key: String, message: String },
+    Obj { key: String, source: ObjError },
+    // Environment maps and image textures
+    Image { key: String, source: image::ImageError },
+    Serialize(toml::ser::Error),
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            SceneError::Parse { key, message } => write!(f, "{}: {}", key, message),
+            SceneError::Invalid { key, message } => write!(f, "{}: {}", key, message),
+            SceneError::Obj { key, source } => write!(f, "{}: {}", key, source),
+            SceneError::Image { key, source } => write!(f, "{}: {}", key, source),
+            SceneError::Serialize(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl Error for SceneError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            SceneError::Io { source, .. } => Some(source),
+            SceneError::Obj { source, .. } => Some(source),
+            SceneError::Image { source, .. } => Some(source),
+            SceneError::Serialize(error) => Some(error),
+            SceneError::Parse { .. } | SceneError::Invalid { .. } => None,
+        }
+    }
+}
+
+fn invalid(key: impl Into<String>, message: impl Into<String>) -> SceneError {
+    SceneError::Invalid { key: key.into(), message: message.into() }
+}
+
+// Everything needed to render, built from a SceneDescription
+pub struct Scene {
+    pub world: World<'static>,
+    pub camera: Camera,
+    pub settings: RenderSettings,
+    // Problems in mesh files that did not stop them from loading
+    pub warnings: Vec<ObjWarning>,
+}
+
+// Serialized form of a scene, written as TOML:
+//
+//     [camera]
+//     look_from = [13, 2, 3]
+//     look_at = [0, 0, 0]
+//     vfov = 20
+//
+//     [render]
+//     width = 900
+//     height = 600
+//
+//     [textures.marble]
+//     type = "noise"
+//     kind = "marble"
+//
+//     [materials.stone]
+//     type = "lambertian"
+//     color = "marble"
+//
+//     [[objects]]
+//     type = "sphere"
+//     center = [0, 1, 0]
+//     radius = 1
+//     material = "stone"
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescription {
+    pub camera: CameraDescription,
+    #[serde(default)]
+    pub render: RenderDescription,
+    #[serde(default)]
+    pub textures: BTreeMap<String, TextureDescription>,
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialDescription>,
+    // Groups of objects that are only rendered through instances
+    #[serde(default)]
+    pub shapes: BTreeMap<String, Vec<ObjectDescription>>,
+    #[serde(default)]
+    pub objects: Vec<ObjectDescription>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDescription {
+    pub look_from: Vec3,
+    pub look_at: Vec3,
+    #[serde(default = "default_vup")]
+    pub vup: Vec3,
+    // Vertical field of view in degrees
+    pub vfov: f64,
+    #[serde(default)]
+    pub aperture: f64,
+    // Defaults to the distance between look_from and look_at
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focus_dist: Option<f64>,
+    // Time span the shutter stays open for motion blur, closed by default
+    #[serde(default)]
+    pub shutter_open: f64,
+    #[serde(default)]
+    pub shutter_close: f64,
+}
+
+fn default_vup() -> Vec3 {
+    Vec3::new(0., 1., 0.)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderDescription {
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: usize,
+    pub max_depth: usize,
+    pub background: BackgroundDescription,
+    // Seed for sampling, the same seed renders the same image
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    // Stops sampling converged pixels early, samples_per_pixel is the most a
+    // pixel gets
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub adaptive: Option<AdaptiveSampling>,
+    // Width and height of the tiles the image is rendered in
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    // How samples are weighted into the pixels around them
+    pub filter: PixelFilter,
+    // Seconds after which the render stops with what it has
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_limit: Option<f64>,
+    // From the linear framebuffer to 8 bit images
+    pub tone_mapping: ToneMapping,
+    // Extra images rendered alongside the color
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub aovs: Vec<Aov>,
+    // Filters the noise out of the finished image
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub denoise: Option<Denoising>,
+}
+
+impl Default for RenderDescription {
+    fn default() -> Self {
+        RenderDescription {
+            width: 900,
+            height: 600,
+            samples_per_pixel: 10,
+            max_depth: 10,
+            background: BackgroundDescription::default(),
+            seed: 0,
+            sampler: SamplerKind::default(),
+            adaptive: None,
+            tile_size: 32,
+            tile_order: TileOrder::default(),
+            filter: PixelFilter::default(),
+            time_limit: None,
+            tone_mapping: ToneMapping::default(),
+            aovs: vec![],
+            denoise: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BackgroundDescription {
+    Solid {
+        color: Vec3,
+    },
+    Gradient {
+        horizon: Vec3,
+        zenith: Vec3,
+    },
+    // Equirectangular .hdr or .exr image, relative paths are resolved against
+    // the scene file
+    Environment {
+        path: PathBuf,
+        // Degrees around the y axis
+        #[serde(default)]
+        rotation: f64,
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+}
+
+// Same sky as Background::default
+impl Default for BackgroundDescription {
+    fn default() -> Self {
+        BackgroundDescription::Gradient {
+            horizon: Vec3::new(1.0, 1.0, 1.0),
+            zenith: Vec3::new(0.5, 0.7, 1.0),
+        }
+    }
+}
+
+impl BackgroundDescription {
+    pub fn to_background(&self, base_dir: &Path) -> Result<Background, SceneError> {
+        match self {
+            BackgroundDescription::Solid { color } => Ok(Background::Solid(*color)),
+            BackgroundDescription::Gradient { horizon, zenith } => Ok(Background::Gradient { horizon: *horizon, zenith: *zenith }),
+            BackgroundDescription::Environment { path, rotation, intensity } => {
+                if !(*intensity >= 0.) {
+                    return Err(invalid("render.background.intensity", "must not be negative"));
+                }
+
+                let map = EnvironmentMap::load(base_dir.join(path))
+                    .map_err(|source| SceneError::Image { key: String::from("render.background.path"), source })?;
+
+                Ok(Background::Environment(Arc::new(map.with_rotation(*rotation).with_intensity(*intensity))))
+            }
+        }
+    }
+}
+
+// Either a constant color or the name of a texture
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum ColorDescription {
+    Color(Vec3),
+    Texture(String),
+}
+
+impl From<Vec3> for ColorDescription {
+    fn from(color: Vec3) -> Self {
+        ColorDescription::Color(color)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TextureDescription {
+    Constant {
+        color: Vec3,
+    },
+    // 3D checkerboard with cells of size scale
+    Checker {
+        even: ColorDescription,
+        odd: ColorDescription,
+        #[serde(default = "default_scale")]
+        scale: f64,
+    },
+    // Relative paths are resolved against the scene file
+    Image {
+        path: PathBuf,
+        #[serde(default)]
+        wrap: WrapMode,
+        #[serde(default)]
+        filter: FilterMode,
+    },
+    Noise {
+        #[serde(default)]
+        kind: NoiseKind,
+        // Frequency of the pattern
+        #[serde(default = "default_scale")]
+        scale: f64,
+        #[serde(default)]
+        seed: u64,
+        #[serde(default = "default_noise_color")]
+        color: Vec3,
+    },
+}
+
+fn default_scale() -> f64 {
+    1.
+}
+
+fn default_noise_color() -> Vec3 {
+    Vec3::constant_new(1.)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum MaterialDescription {
+    Lambertian {
+        color: ColorDescription,
+    },
+    Metal {
+        color: ColorDescription,
+        #[serde(default)]
+        fuzz: f64,
+    },
+    Dielectric {
+        // Index of refraction
+        ior: f64,
+    },
+    DiffuseLight {
+        color: Vec3,
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+    // For media, scatters in every direction
+    Isotropic {
+        color: ColorDescription,
+    },
+}
+
+fn default_intensity() -> f64 {
+    1.
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ObjectDescription {
+    Sphere {
+        center: Vec3,
+        radius: f64,
+        material: String,
+    },
+    // Sphere moving from center0 at time0 to center1 at time1
+    MovingSphere {
+        center0: Vec3,
+        center1: Vec3,
+        #[serde(default)]
+        time0: f64,
+        #[serde(default = "default_time1")]
+        time1: f64,
+        radius: f64,
+        material: String,
+    },
+    // Infinite plane through point
+    Plane {
+        point: Vec3,
+        normal: Vec3,
+        material: String,
+    },
+    // Rectangle spanning min to max in the two named axes, at offset along
+    // the third one
+    Rect {
+        axes: RectAxes,
+        min: [f64; 2],
+        max: [f64; 2],
+        offset: f64,
+        material: String,
+    },
+    // Axis-aligned box between two corners
+    Box {
+        min: Vec3,
+        max: Vec3,
+        material: String,
+    },
+    // The named shape, transformed by the steps in order
+    Instance {
+        shape: String,
+        #[serde(default)]
+        transform: Vec<TransformDescription>,
+    },
+    // The named shape following keyframes, interpolated by time
+    AnimatedInstance {
+        shape: String,
+        keyframes: Vec<KeyframeDescription>,
+    },
+    // Fog or smoke filling the named shape, which has to be convex
+    Medium {
+        shape: String,
+        density: f64,
+        material: String,
+    },
+    Triangle {
+        vertices: [Vec3; 3],
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        normals: Option<[Vec3; 3]>,
+        material: String,
+    },
+    // Wavefront .obj file, relative paths are resolved against the scene file.
+    // Uses the .mtl materials unless a material is given.
+    Mesh {
+        path: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        material: Option<String>,
+    },
+}
+
+// One step of an instance transform, written as e.g.
+// { rotate = { axis = [0, 1, 0], angle = 15 } }
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum TransformDescription {
+    Translate(Vec3),
+    Scale(Vec3),
+    Rotate(RotationDescription),
+}
+
+// Degrees counterclockwise around axis
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RotationDescription {
+    pub axis: Vec3,
+    pub angle: f64,
+}
+
+// Pose of an animated instance, scaled, then rotated, then translated
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct KeyframeDescription {
+    pub time: f64,
+    #[serde(default = "default_scale_factors")]
+    pub scale: Vec3,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rotate: Option<RotationDescription>,
+    #[serde(default = "default_translation")]
+    pub translate: Vec3,
+}
+
+fn default_time1() -> f64 {
+    1.
+}
+
+fn default_scale_factors() -> Vec3 {
+    Vec3::constant_new(1.)
+}
+
+fn default_translation() -> Vec3 {
+    Vec3::constant_new(0.)
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum RectAxes {
+    Xy,
+    Xz,
+    Yz,
+}
+
+impl SceneDescription {
+    pub fn from_toml(source: &str) -> Result<SceneDescription, SceneError> {
+        let deserializer = toml::Deserializer::new(source);
+
+        serde_path_to_error::deserialize(deserializer).map_err(|error| {
+            let key = error.path().to_string();
+            SceneError::Parse { key, message: error.into_inner().to_string() }
+        })
+    }
+
+    pub fn to_toml(&self) -> Result<String, SceneError> {
+        toml::to_string(self).map_err(SceneError::Serialize)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<SceneDescription, SceneError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)
+            .map_err(|source| SceneError::Io { path: path.to_path_buf(), source })?;
+
+        SceneDescription::from_toml(&source)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SceneError> {
+        let path = path.as_ref();
+        fs::write(path, self.to_toml()?)
+            .map_err(|source| SceneError::Io { path: path.to_path_buf(), source })
+    }
+
+    // Validates the description and builds everything needed for rendering,
+    // mesh paths are resolved against base_dir
+    pub fn build(&self, base_dir: &Path) -> Result<Scene, SceneError> {
+        let settings = self.settings(base_dir)?;
+        let camera = self.camera(settings.aspect_ratio())?;
+        let (world, warnings) = self.build_world_with_warnings(base_dir)?;
+
+        Ok(Scene { world, camera, settings, warnings })
+    }
+
+    // Background image paths are resolved against base_dir
+    pub fn settings(&self, base_dir: &Path) -> Result<RenderSettings, SceneError> {
+        let render = &self.render;
+
+        // The pixel to viewport mapping divides by size - 1
+        for (key, value) in [("width", render.width), ("height", render.height)] {
+            if value < 2 {
+                return Err(invalid(format!("render.{}", key), "must be at least 2"));
+            }
+        }
+        if render.samples_per_pixel == 0 {
+            return Err(invalid("render.samples_per_pixel", "must be greater than 0"));
+        }
+
+        if render.tile_size == 0 {
+            return Err(invalid("render.tile_size", "must be greater than 0"));
+        }
+        // Smaller filters would miss samples of their own pixel
+        if let Some(radius) = render.filter.radius {
+            if !(radius >= 0.5 && radius.is_finite()) {
+                return Err(invalid("render.filter.radius", "must be at least 0.5 pixels"));
+            }
+        }
+        let time_limit = match render.time_limit {
+            Some(seconds) => Some(Duration::try_from_secs_f64(seconds)
+                .map_err(|_| invalid("render.time_limit", "must be a number of seconds, at least 0"))?),
+            None => None,
+        };
+        if !render.tone_mapping.exposure.is_finite() {
+            return Err(invalid("render.tone_mapping.exposure", "must be a finite number of stops"));
+        }
+        if !(render.tone_mapping.white_point > 0. && render.tone_mapping.white_point.is_finite()) {
+            return Err(invalid("render.tone_mapping.white_point", "must be greater than 0"));
+        }
+        for (index, aov) in render.aovs.iter().enumerate() {
+            if render.aovs[..index].contains(aov) {
+                return Err(invalid(format!("render.aovs[{}]", index), format!("lists {} twice", aov.name())));
+            }
+        }
+        if let Some(denoise) = &render.denoise {
+            if denoise.passes == 0 {
+                return Err(invalid("render.denoise.passes", "must be greater than 0"));
+            }
+            if !(denoise.color_sigma > 0.) {
+                return Err(invalid("render.denoise.color_sigma", "must be greater than 0"));
+            }
+            if !(denoise.albedo_sigma > 0.) {
+                return Err(invalid("render.denoise.albedo_sigma", "must be greater than 0"));
+            }
+            if !(denoise.normal_power >= 0.) {
+                return Err(invalid("render.denoise.normal_power", "must not be negative"));
+            }
+        }
+        if let Some(adaptive) = &render.adaptive {
+            if !(adaptive.noise_threshold > 0.) {
+                return Err(invalid("render.adaptive.noise_threshold", "must be greater than 0"));
+            }
+            // The variance needs at least two samples
+            if adaptive.min_samples < 2 {
+                return Err(invalid("render.adaptive.min_samples", "must be at least 2"));
+            }
+        }
+
+        let mut settings = RenderSettings::new(render.width, render.height, render.samples_per_pixel, render.max_depth);
+        settings.background = render.background.to_background(base_dir)?;
+        settings.seed = render.seed;
+        settings.sampler = render.sampler;
+        settings.adaptive = render.adaptive;
+        settings.tile_size = render.tile_size;
+        settings.tile_order = render.tile_order;
+        settings.filter = render.filter;
+        settings.time_limit = time_limit;
+        settings.tone_mapping = render.tone_mapping;
+        settings.aovs = render.aovs.clone();
+        // Passes past the one whose step spans the image only filter each
+        // pixel with itself
+        let max_denoise_passes = (usize::BITS - render.width.max(render.height).leading_zeros()) as usize;
+        settings.denoise = render.denoise.map(|denoise| Denoising { passes: denoise.passes.min(max_denoise_passes), ..denoise });
+
+        Ok(settings)
+    }
+
+    pub fn camera(&self, aspect_ratio: f64) -> Result<Camera, SceneError> {
+        let camera = &self.camera;
+
+        if !(camera.vfov > 0. && camera.vfov < 180.) {
+            return Err(invalid("camera.vfov", "must be between 0 and 180 degrees"));
+        }
+        if !(camera.aperture >= 0.) {
+            return Err(invalid("camera.aperture", "must not be negative"));
+        }
+
+        let view = camera.look_from - camera.look_at;
+        if !(view.length_squared() > 0.) {
+            return Err(invalid("camera.look_at", "must differ from look_from"));
+        }
+        if !(Vec3::cross(&camera.vup, &view).length_squared() > 0.) {
+            return Err(invalid("camera.vup", "must not be parallel to the viewing direction"));
+        }
+
+        let focus_dist = camera.focus_dist.unwrap_or_else(|| view.length());
+        if !(focus_dist > 0.) {
+            return Err(invalid("camera.focus_dist", "must be greater than 0"));
+        }
+
+        if !(camera.shutter_close >= camera.shutter_open) {
+            return Err(invalid("camera.shutter_close", "must not be before shutter_open"));
+        }
+
+        Ok(Camera::new(camera.look_from, camera.look_at, camera.vup, camera.vfov,
+                       aspect_ratio, camera.aperture, focus_dist)
+            .with_shutter(camera.shutter_open, camera.shutter_close))
+    }
+
+    // Mesh and image texture paths are resolved against base_dir. Warnings
+    // about mesh files are dropped, build keeps them.
+    pub fn build_world(&self, base_dir: &Path) -> Result<World<'static>, SceneError> {
+        Ok(self.build_world_with_warnings(base_dir)?.0)
+    }
+
+    fn build_world_with_warnings(&self, base_dir: &Path) -> Result<(World<'static>, Vec<ObjWarning>), SceneError> {
+        let textures = self.build_textures(base_dir)?;
+
+        let mut materials = Materials::new();
+        for (name, material) in &self.materials {
+            material.validate(&format!("materials.{}", name), &textures)?;
+            materials.insert(name.clone(), material.to_material(&textures));
+        }
+
+        let mut builder = WorldBuilder {
+            shape_descriptions: &self.shapes,
+            base_dir,
+            materials,
+            mesh_materials: vec![],
+            warnings: vec![],
+            shapes: BTreeMap::new(),
+            in_progress: vec![],
+        };
+
+        for name in self.shapes.keys() {
+            builder.shape(name)?;
+        }
+
+        let mut world = builder.objects("objects", &self.objects)?;
+        // Material ids go by name, then meshes keeping their own materials
+        // in the order they were loaded
+        for material in builder.materials.values().chain(&builder.mesh_materials) {
+            world.register_material(material);
+        }
+
+        Ok((world, builder.warnings))
+    }
+
+    // Builds every texture once so materials can share them
+    fn build_textures(&self, base_dir: &Path) -> Result<Textures, SceneError> {
+        let mut builder = TextureBuilder {
+            descriptions: &self.textures,
+            base_dir,
+            built: Textures::new(),
+            in_progress: vec![],
+        };
+
+        for name in self.textures.keys() {
+            builder.texture(name)?;
+        }
+
+        Ok(builder.built)
+    }
+}
+
+// Shapes can be instanced by other shapes, so like textures they are built
+// depth first
+struct WorldBuilder<'a> {
+    shape_descriptions: &'a BTreeMap<String, Vec<ObjectDescription>>,
+    base_dir: &'a Path,
+    materials: Materials,
+    // Of meshes without a scene material, from their .mtl files
+    mesh_materials: Vec<Arc<dyn Material>>,
+    warnings: Vec<ObjWarning>,
+    shapes: BTreeMap<String, Arc<dyn Hittable>>,
+    in_progress: Vec<&'a str>,
+}
+
+impl<'a> WorldBuilder<'a> {
+    // Name must be a key of shape_descriptions
+    fn shape(&mut self, name: &'a str) -> Result<Arc<dyn Hittable>, SceneError> {
+        if let Some(shape) = self.shapes.get(name) {
+            return Ok(shape.clone());
+        }
+
+        let key = format!("shapes.{}", name);
+        if self.in_progress.contains(&name) {
+            return Err(invalid(key, "instances itself"));
+        }
+        self.in_progress.push(name);
+
+        let mut world = self.objects(&key, &self.shape_descriptions[name])?;
+        world.build_bvh();
+        let shape: Arc<dyn Hittable> = Arc::new(world);
+
+        self.in_progress.pop();
+        self.shapes.insert(name.to_string(), shape.clone());
+
+        Ok(shape)
+    }
+
+    // Looks up the named shape for the object at key
+    fn object_shape(&mut self, key: &str, name: &'a str) -> Result<Arc<dyn Hittable>, SceneError> {
+        if !self.shape_descriptions.contains_key(name) {
+            return Err(invalid(format!("{}.shape", key), format!("unknown shape '{}'", name)));
+        }
+        self.shape(name)
+    }
+
+    fn objects(&mut self, key: &str, objects: &'a [ObjectDescription]) -> Result<World<'static>, SceneError> {
+        let mut world = World::new();
+        for (index, object) in objects.iter().enumerate() {
+            self.object(&format!("{}[{}]", key, index), object, &mut world)?;
+        }
+        Ok(world)
+    }
+
+    fn object(&mut self, key: &str, object: &'a ObjectDescription, world: &mut World<'static>) -> Result<(), SceneError> {
+        match object {
+            ObjectDescription::Sphere { center, radius, material: name } => {
+                if !(*radius > 0.) {
+                    return Err(invalid(format!("{}.radius", key), "must be greater than 0"));
+                }
+                world.add(Box::new(Sphere::new(*center, *radius, material(&self.materials, key, name)?)));
+            }
+            ObjectDescription::MovingSphere { center0, center1, time0, time1, radius, material: name } => {
+                if !(*radius > 0.) {
+                    return Err(invalid(format!("{}.radius", key), "must be greater than 0"));
+                }
+                if !(time1 >= time0) {
+                    return Err(invalid(format!("{}.time1", key), "must not be before time0"));
+                }
+                let material = material(&self.materials, key, name)?;
+                world.add(Box::new(MovingSphere::new(*center0, *center1, *time0, *time1, *radius, material)));
+            }
+            ObjectDescription::Plane { point, normal, material: name } => {
+                if !(normal.length_squared() > 0.) {
+                    return Err(invalid(format!("{}.normal", key), "must not be zero"));
+                }
+                world.add(Box::new(Plane::new(*point, *normal, material(&self.materials, key, name)?)));
+            }
+            ObjectDescription::Rect { axes, min, max, offset, material: name } => {
+                if !(min[0] < max[0] && min[1] < max[1]) {
+                    return Err(invalid(format!("{}.max", key), "must be greater than min"));
+                }
+                let material = material(&self.materials, key, name)?;
+                let rect = match axes {
+                    RectAxes::Xy => AxisRect::xy(min[0], max[0], min[1], max[1], *offset, material),
+                    RectAxes::Xz => AxisRect::xz(min[0], max[0], min[1], max[1], *offset, material),
+                    RectAxes::Yz => AxisRect::yz(min[0], max[0], min[1], max[1], *offset, material),
+                };
+                world.add(Box::new(rect));
+            }
+            ObjectDescription::Box { min, max, material: name } => {
+                if !(min.x() < max.x() && min.y() < max.y() && min.z() < max.z()) {
+                    return Err(invalid(format!("{}.max", key), "must be greater than min"));
+                }
+                world.add(Box::new(BoxShape::new(*min, *max, material(&self.materials, key, name)?)));
+            }
+            ObjectDescription::Instance { shape, transform } => {
+                let shape = self.object_shape(key, shape)?;
+                world.add(Box::new(Instance::new(shape, transform_from(key, transform)?)));
+            }
+            ObjectDescription::AnimatedInstance { shape, keyframes } => {
+                if keyframes.is_empty() {
+                    return Err(invalid(format!("{}.keyframes", key), "needs at least one keyframe"));
+                }
+                // Interpolating between keyframes at the same time divides by 0
+                for (index, keyframe) in keyframes.iter().enumerate() {
+                    let key = format!("{}.keyframes[{}].time", key, index);
+                    if !keyframe.time.is_finite() {
+                        return Err(invalid(key, "must be a finite number"));
+                    }
+                    if index > 0 && !(keyframe.time > keyframes[index - 1].time) {
+                        return Err(invalid(key, "must be later than the time of the keyframe before it"));
+                    }
+                }
+                let keyframes = keyframes
+                    .iter()
+                    .enumerate()
+                    .map(|(index, keyframe)| keyframe_from(&format!("{}.keyframes[{}]", key, index), keyframe))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let shape = self.object_shape(key, shape)?;
+                world.add(Box::new(AnimatedInstance::new(shape, KeyframedTransform::new(keyframes))));
+            }
+            ObjectDescription::Medium { shape, density, material: name } => {
+                if !(*density > 0.) {
+                    return Err(invalid(format!("{}.density", key), "must be greater than 0"));
+                }
+                let shape = self.object_shape(key, shape)?;
+                world.add(Box::new(ConstantMedium::new(shape, *density, material(&self.materials, key, name)?)));
+            }
+            ObjectDescription::Triangle { vertices, normals, material: name } => {
+                let triangle = Triangle::new(*vertices, material(&self.materials, key, name)?);
+                match normals {
+                    Some(normals) => world.add(Box::new(triangle.with_normals(*normals))),
+                    None => world.add(Box::new(triangle)),
+                }
+            }
+            ObjectDescription::Mesh { path, material: name } => {
+                let ObjMeshes { meshes, warnings } = load_meshes(self.base_dir.join(path))
+                    .map_err(|source| SceneError::Obj { key: format!("{}.path", key), source })?;
+                self.warnings.extend(warnings);
+
+                for obj_mesh in meshes {
+                    let mesh = match name {
+                        Some(name) => obj_mesh.mesh.with_material(material(&self.materials, key, name)?),
+                        None => {
+                            self.mesh_materials.push(obj_mesh.mesh.material().clone());
+                            obj_mesh.mesh
+                        }
+                    };
+                    world.add(Box::new(mesh));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Composes the steps of the instance at key, the first step is applied first
+fn transform_from(key: &str, steps: &[TransformDescription]) -> Result<Transform, SceneError> {
+    steps.iter().enumerate().try_fold(Transform::identity(), |transform, (index, step)| {
+        let key = format!("{}.transform[{}]", key, index);
+        let step = match step {
+            TransformDescription::Translate(offset) => Transform::translation(*offset),
+            TransformDescription::Scale(factors) => {
+                validate_scale(&format!("{}.scale", key), factors)?;
+                Transform::scaling(*factors)
+            }
+            TransformDescription::Rotate(rotation) => {
+                validate_rotation(&format!("{}.rotate", key), rotation)?;
+                Transform::rotation(rotation.axis, rotation.angle)
+            }
+        };
+
+        Ok(step * transform)
+    })
+}
+
+fn keyframe_from(key: &str, keyframe: &KeyframeDescription) -> Result<Keyframe, SceneError> {
+    validate_scale(&format!("{}.scale", key), &keyframe.scale)?;
+
+    let mut result = Keyframe::new(keyframe.time)
+        .with_scale(keyframe.scale)
+        .with_translation(keyframe.translate);
+    if let Some(rotation) = &keyframe.rotate {
+        validate_rotation(&format!("{}.rotate", key), rotation)?;
+        result = result.with_rotation(rotation.axis, rotation.angle);
+    }
+
+    Ok(result)
+}
+
+fn validate_scale(key: &str, factors: &Vec3) -> Result<(), SceneError> {
+    if !(factors.x().abs() > 0. && factors.y().abs() > 0. && factors.z().abs() > 0.) {
+        return Err(invalid(key, "must not be zero along any axis"));
+    }
+    Ok(())
+}
+
+fn validate_rotation(key: &str, rotation: &RotationDescription) -> Result<(), SceneError> {
+    if !(rotation.axis.length_squared() > 0.) {
+        return Err(invalid(format!("{}.axis", key), "must not be zero"));
+    }
+    Ok(())
+}
+
+type Textures = BTreeMap<String, Arc<dyn Texture>>;
+type Materials = BTreeMap<String, Arc<dyn Material>>;
+
+// Looks up the named material for the object at key
+fn material(materials: &Materials, key: &str, name: &str) -> Result<Arc<dyn Material>, SceneError> {
+    materials
+        .get(name)
+        .cloned()
+        .ok_or_else(|| invalid(format!("{}.material", key), format!("unknown material '{}'", name)))
+}
+
+// Textures can refer to each other by name, so they are built depth first
+struct TextureBuilder<'a> {
+    descriptions: &'a BTreeMap<String, TextureDescription>,
+    base_dir: &'a Path,
+    built: Textures,
+    // Names being built, to catch textures that end up referring to themselves
+    in_progress: Vec<&'a str>,
+}
+
+impl<'a> TextureBuilder<'a> {
+    // Name must be a key of descriptions
+    fn texture(&mut self, name: &'a str) -> Result<Arc<dyn Texture>, SceneError> {
+        if let Some(texture) = self.built.get(name) {
+            return Ok(texture.clone());
+        }
+
+        let key = format!("textures.{}", name);
+        if self.in_progress.contains(&name) {
+            return Err(invalid(key, "refers to itself"));
+        }
+        self.in_progress.push(name);
+
+        let texture: Arc<dyn Texture> = match &self.descriptions[name] {
+            TextureDescription::Constant { color } => Arc::new(SolidColor::new(*color)),
+            TextureDescription::Checker { even, odd, scale } => {
+                if !(*scale > 0.) {
+                    return Err(invalid(format!("{}.scale", key), "must be greater than 0"));
+                }
+                let even = self.color(&format!("{}.even", key), even)?;
+                let odd = self.color(&format!("{}.odd", key), odd)?;
+                Arc::new(Checker::new(even, odd, *scale))
+            }
+            TextureDescription::Image { path, wrap, filter } => {
+                let image = ImageTexture::load(self.base_dir.join(path))
+                    .map_err(|source| SceneError::Image { key: format!("{}.path", key), source })?;
+                Arc::new(image.with_wrap(*wrap).with_filter(*filter))
+            }
+            TextureDescription::Noise { kind, scale, seed, color } => {
+                if !(*scale > 0.) {
+                    return Err(invalid(format!("{}.scale", key), "must be greater than 0"));
+                }
+                Arc::new(NoiseTexture::new(*kind, *scale, *seed).with_color(*color))
+            }
+        };
+
+        self.in_progress.pop();
+        self.built.insert(name.to_string(), texture.clone());
+
+        Ok(texture)
+    }
+
+    fn color(&mut self, key: &str, color: &'a ColorDescription) -> Result<Arc<dyn Texture>, SceneError> {
+        match color {
+            ColorDescription::Color(color) => Ok(Arc::new(SolidColor::new(*color))),
+            ColorDescription::Texture(name) if self.descriptions.contains_key(name) => self.texture(name),
+            ColorDescription::Texture(name) => Err(invalid(key, format!("unknown texture '{}'", name))),
+        }
+    }
+}
+
+impl ColorDescription {
+    fn validate(&self, key: &str, textures: &Textures) -> Result<(), SceneError> {
+        match self {
+            ColorDescription::Texture(name) if !textures.contains_key(name) => {
+                Err(invalid(key, format!("unknown texture '{}'", name)))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Texture names must be in textures, see validate
+    fn to_texture(&self, textures: &Textures) -> Arc<dyn Texture> {
+        match self {
+            ColorDescription::Color(color) => Arc::new(SolidColor::new(*color)),
+            ColorDescription::Texture(name) => textures[name].clone(),
+        }
+    }
+}
+
+impl MaterialDescription {
+    fn validate(&self, key: &str, textures: &Textures) -> Result<(), SceneError> {
+        match self {
+            MaterialDescription::Lambertian { color }
+            | MaterialDescription::Metal { color, .. }
+            | MaterialDescription::Isotropic { color } => {
+                color.validate(&format!("{}.color", key), textures)?;
+            }
+            _ => (),
+        }
+
+        match self {
+            MaterialDescription::Metal { fuzz, .. } if !(0. ..=1.).contains(fuzz) => {
+                Err(invalid(format!("{}.fuzz", key), "must be between 0 and 1"))
+            }
+            MaterialDescription::Dielectric { ior } if !(*ior > 0.) => {
+                Err(invalid(format!("{}.ior", key), "must be greater than 0"))
+            }
+            MaterialDescription::DiffuseLight { intensity, .. } if !(*intensity >= 0.) => {
+                Err(invalid(format!("{}.intensity", key), "must not be negative"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // The material must have passed validate against the same textures
+    fn to_material(&self, textures: &Textures) -> Arc<dyn Material> {
+        match self {
+            MaterialDescription::Lambertian { color } => Arc::new(Lambertian::textured(color.to_texture(textures))),
+            MaterialDescription::Metal { color, fuzz } => Arc::new(Metal::textured(color.to_texture(textures), *fuzz)),
+            MaterialDescription::Dielectric { ior } => Arc::new(Dielectric::new(*ior)),
+            MaterialDescription::DiffuseLight { color, intensity } => Arc::new(DiffuseLight::new(*color, *intensity)),
+            MaterialDescription::Isotropic { color } => Arc::new(Isotropic::textured(color.to_texture(textures))),
+        }
+    }
+}
+
+// Final scene of Ray Tracing in One Weekend, a grid of (2 * number)^2 small
+// random spheres around three large ones. The same seed gives the same scene.
+pub fn random_scene_description(number: isize, seed: u64) -> SceneDescription {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut materials = BTreeMap::new();
+    let mut objects = vec![];
+
+    materials.insert(String::from("ground"), MaterialDescription::Lambertian { color: Vec3::new(0.5, 0.5, 0.5).into() });
+    objects.push(ObjectDescription::Plane {
+        point: Vec3::new(0., 0., 0.),
+        normal: Vec3::new(0., 1., 0.),
+        material: String::from("ground"),
+    });
+
+    let mut add = |name: String, material: MaterialDescription, center: Vec3, radius: f64| {
+        materials.insert(name.clone(), material);
+        objects.push(ObjectDescription::Sphere { center, radius, material: name });
+    };
+
+    for a in -number..number {
+        for b in -number..number {
+            let material_selector: f64 = rng.gen();
+            let center_point = Vec3::new(a as f64 + 0.9*rng.gen::<f64>(), 0.2, b as f64 + 0.9*rng.gen::<f64>());
+
+            if (center_point - Vec3::new(4., 0.2, 0.)).length() > 0.9 {
+                let material = if material_selector < 0.8 {
+                    let color = Vec3::new(rng.gen(), rng.gen(), rng.gen()) * Vec3::new(rng.gen(), rng.gen(), rng.gen());
+                    MaterialDescription::Lambertian { color: color.into() }
+                } else if material_selector < 0.95 {
+                    let color = Vec3::new(rng.gen_range(0.5..1.), rng.gen_range(0.5..1.), rng.gen_range(0.5..1.));
+                    let fuzz = rng.gen_range(0. ..0.5);
+                    MaterialDescription::Metal { color: color.into(), fuzz }
+                } else {
+                    MaterialDescription::Dielectric { ior: 1.5 }
+                };
+
+                add(format!("sphere_{}_{}", a, b), material, center_point, 0.2);
+            }
+        }
+    }
+
+    add(String::from("glass"), MaterialDescription::Dielectric { ior: 1.5 },
+        Vec3::new(0., 1., 0.), 1.);
+    add(String::from("brown"), MaterialDescription::Lambertian { color: Vec3::new(0.4, 0.2, 0.1).into() },
+        Vec3::new(-4., 1., 0.), 1.);
+    add(String::from("bronze"), MaterialDescription::Metal { color: Vec3::new(0.7, 0.6, 0.5).into(), fuzz: 0. },
+        Vec3::new(4., 1., 0.), 1.);
+
+    SceneDescription {
+        camera: CameraDescription {
+            look_from: Vec3::new(13., 2., 3.),
+            look_at: Vec3::new(0., 0., 0.),
+            vup: default_vup(),
+            vfov: 20.,
+            aperture: 0.1,
+            focus_dist: Some(10.),
+            shutter_open: 0.,
+            shutter_close: 0.,
+        },
+        render: RenderDescription::default(),
+        textures: BTreeMap::new(),
+        materials,
+        shapes: BTreeMap::new(),
+        objects,
+    }
+}
+
+// Loads a scene file, resolving mesh paths relative to it
+pub fn load_scene<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let description = SceneDescription::load(path)?;
+
+    description.build(path.parent().unwrap_or_else(|| Path::new("")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAMERA: &str = "\
+[camera]
+look_from = [0, 0, 5]
+look_at = [0, 0, 0]
+vfov = 40
+";
+
+    fn parse(source: &str) -> Result<SceneDescription, SceneError> {
+        SceneDescription::from_toml(&format!("{}{}", CAMERA, source))
+    }
+
+    // Key and message of a parse or validation error
+    fn error(result: Result<impl fmt::Debug, SceneError>) -> (String, String) {
+        match result {
+            Err(SceneError::Parse { key, message }) | Err(SceneError::Invalid { key, message }) => (key, message),
+            Err(error) => panic!("expected a parse or validation error, got {}", error),
+            Ok(value) => panic!("expected an error, got {:?}", value),
+        }
+    }
+
+    fn build_error(source: &str) -> (String, String) {
+        error(parse(source).unwrap().build(Path::new("")).map(|_| ()))
+    }
+
+    #[test]
+    fn parse_errors_name_the_key() {
+        let (key, _) = error(parse("[render]\nwidth = \"wide\"\n"));
+        assert_eq!(key, "render.width");
+
+        let (key, _) = error(parse("[render.tone_mapping]\nexposure = \"bright\"\n"));
+        assert_eq!(key, "render.tone_mapping.exposure");
+
+        // Tagged enums are parsed as a whole, the key ends at them
+        let (key, message) = error(parse("[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = \"big\"\nmaterial = \"red\"\n"));
+        assert_eq!(key, "objects[0]");
+        assert!(message.contains("big"), "{}", message);
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let (_, message) = error(parse("[render]\nwidht = 100\n"));
+        assert!(message.contains("widht"), "{}", message);
+
+        let (key, message) = error(parse("[materials.red]\ntype = \"lambertian\"\ncolor = [1, 0, 0]\nshiny = true\n"));
+        assert_eq!(key, "materials.red");
+        assert!(message.contains("shiny"), "{}", message);
+
+        let (_, message) = error(SceneDescription::from_toml(&format!("{}lens = \"fisheye\"\n", CAMERA)));
+        assert!(message.contains("lens"), "{}", message);
+    }
+
+    #[test]
+    fn invalid_values_name_the_key() {
+        let material = "[materials.red]\ntype = \"lambertian\"\ncolor = [1, 0, 0]\n";
+        let sphere = |radius: &str| format!("{}[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = {}\nmaterial = \"red\"\n", material, radius);
+
+        assert_eq!(build_error(&sphere("0")), (String::from("objects[0].radius"), String::from("must be greater than 0")));
+        assert_eq!(build_error(&sphere("nan")).0, "objects[0].radius");
+        assert_eq!(build_error("[render]\nwidth = 1\n").0, "render.width");
+        assert_eq!(build_error("[render.adaptive]\nnoise_threshold = nan\n").0, "render.adaptive.noise_threshold");
+        assert_eq!(build_error("[render.denoise]\ncolor_sigma = nan\n").0, "render.denoise.color_sigma");
+        assert_eq!(build_error("[materials.glass]\ntype = \"dielectric\"\nior = nan\n").0, "materials.glass.ior");
+        assert_eq!(build_error("[[objects]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"blue\"\n").0,
+                   "objects[0].material");
+    }
+
+    #[test]
+    fn denoise_passes_stop_at_the_image_size() {
+        let passes = |passes: usize| {
+            let description = parse(&format!("[render]\nwidth = 40\nheight = 20\n[render.denoise]\npasses = {}\n", passes)).unwrap();
+            description.settings(Path::new("")).unwrap().denoise.unwrap().passes
+        };
+
+        assert_eq!(passes(3), 3);
+        // Steps 1 to 32, the next would be wider than the image
+        assert_eq!(passes(6), 6);
+        assert_eq!(passes(7), 6);
+        assert_eq!(passes(40), 6);
+        assert_eq!(passes(i64::MAX as usize), 6);
+    }
+
+    #[test]
+    fn descriptions_survive_a_toml_round_trip() {
+        let descriptions = [
+            random_scene_description(3, 7),
+            SceneDescription::load(Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes/motion_blur.toml")).unwrap(),
+        ];
+
+        for description in descriptions {
+            let toml = description.to_toml().unwrap();
+            let reparsed = SceneDescription::from_toml(&toml).unwrap();
+            assert_eq!(reparsed.to_toml().unwrap(), toml);
+        }
+    }
+
+    #[test]
+    fn every_bundled_scene_loads() {
+        let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenes");
+        let mut count = 0;
+
+        for entry in fs::read_dir(&directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_some_and(|extension| extension == "toml") {
+                let scene = load_scene(&path).unwrap_or_else(|error| panic!("{}: {}", path.display(), error));
+                assert!(!scene.world.is_empty(), "{}", path.display());
+                count += 1;
+            }
+        }
+
+        assert!(count > 0);
+    }
+}
@@ -0,0 +1,75 @@
+use crate::background::Background;
+use crate::sampler::SamplerKind;
+use crate::adaptive::AdaptiveSampling;
+use crate::tile::TileOrder;
+use crate::tonemap::ToneMapping;
+use crate::aov::Aov;
+use crate::denoise::Denoising;
+use crate::filter::PixelFilter;
+
+use std::time::Duration;
+
+pub struct RenderSettings {
+    pub image_width: usize,
+    pub image_height: usize,
+    pub samples_per_pixel: usize,
+    // Maximum number of bounces per camera ray
+    pub max_depth: usize,
+    pub background: Background,
+    // The same seed renders the same image
+    pub seed: u64,
+    pub sampler: SamplerKind,
+    // Every pixel gets samples_per_pixel samples without it
+    pub adaptive: Option<AdaptiveSampling>,
+    // Width and height of the tiles the image is rendered in
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    // How samples are weighted into the pixels around them. AOVs are always
+    // the mean of the samples of their own pixel.
+    pub filter: PixelFilter,
+    // Wall clock time after which the render stops with what it has
+    pub time_limit: Option<Duration>,
+    // From the linear framebuffer to 8 bit images
+    pub tone_mapping: ToneMapping,
+    // Rendered alongside the color, together they cost one more ray per
+    // sample
+    pub aovs: Vec<Aov>,
+    // Filters the finished image, renders the albedo and normal AOVs it
+    // needs even if aovs does not ask for them
+    pub denoise: Option<Denoising>,
+}
+
+impl RenderSettings {
+    pub fn new(image_width: usize, image_height: usize, samples_per_pixel: usize, max_depth: usize) -> RenderSettings {
+        RenderSettings {
+            image_width,
+            image_height,
+            samples_per_pixel,
+            max_depth,
+            background: Background::default(),
+            seed: 0,
+            sampler: SamplerKind::default(),
+            adaptive: None,
+            tile_size: 32,
+            tile_order: TileOrder::default(),
+            filter: PixelFilter::default(),
+            time_limit: None,
+            tone_mapping: ToneMapping::default(),
+            aovs: vec![],
+            denoise: None,
+        }
+    }
+
+    // width over height
+    pub fn aspect_ratio(&self) -> f64 {
+        self.image_width as f64 / self.image_height as f64
+    }
+
+    // aovs, then the guides of the denoiser that are missing from them
+    pub(crate) fn rendered_aovs(&self) -> Vec<Aov> {
+        let guides = if self.denoise.is_some() { &[Aov::Albedo, Aov::Normal][..] } else { &[] };
+        let missing_guides = guides.iter().filter(|aov| !self.aovs.contains(aov));
+
+        self.aovs.iter().chain(missing_guides).copied().collect()
+    }
+}
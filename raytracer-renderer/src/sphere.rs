@@ -2,15 +2,19 @@ use crate::hittable::{ Hittable, HitRecord };
 use crate::vec3::Vec3;
 use crate::ray::Ray;
 use crate::material::Material;
+use crate::aabb::Aabb;
+
+use std::f64::consts::PI;
+use std::sync::Arc;
 
 pub struct Sphere {
     center: Vec3,
     radius: f64,
-    material: Box<dyn Material>,
+    material: Arc<dyn Material>,
 }
 
 impl Sphere {
-    pub fn new(center: Vec3, radius: f64, material: Box<dyn Material>) -> Sphere {
+    pub fn new(center: Vec3, radius: f64, material: Arc<dyn Material>) -> Sphere {
         Sphere {
             center,
             radius,
@@ -19,38 +23,109 @@ impl Sphere {
     }
 }
 
+impl Sphere {
+    // Latitude-longitude coordinates of a point on the unit sphere, u goes
+    // around the y axis starting at -x and v from the bottom pole to the top
+    fn uv(point: Vec3) -> (f64, f64) {
+        let theta = f64::acos(-point.y().clamp(-1., 1.));
+        let phi = f64::atan2(-point.z(), point.x()) + PI;
+
+        (phi / (2. * PI), theta / PI)
+    }
+}
+
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = ray.origin() - self.center;
-        let a = ray.direction().length_squared();
-        let half_b = Vec3::dot(&oc, &ray.direction());
-        let c = oc.length_squared() - self.radius * self.radius;
-        let discriminant = half_b * half_b - a * c;
-
-        if discriminant >= 0. {
-            let sqrt_discriminant = discriminant.sqrt();
-            let root_neg = (-half_b - sqrt_discriminant) / a;
-            let root_pos = (-half_b + sqrt_discriminant) / a;
-
-            for root in [root_neg, root_pos].iter() {
-                if *root < t_max && *root > t_min {
-                    let point = ray.at(*root);
-                    let normal = (point - self.center) / self.radius;
-                    let front_face = Vec3::dot(&ray.direction(), &normal) < 0.0;
-
-                    let normal = if front_face { normal } else { -normal };
-
-                    return Some(HitRecord::new(
-                        point,
-                        normal,
-                        &(*self.material),
-                        *root,
-                        front_face,
-                    ));
-                }
-            }
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        hit_sphere(self.center, self.radius, &*self.material, ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(sphere_box(self.center, self.radius))
+    }
+}
+
+// Sphere moving in a straight line from center0 at time0 to center1 at time1.
+// It rests at the nearer end for ray times outside that span.
+pub struct MovingSphere {
+    center0: Vec3,
+    center1: Vec3,
+    time0: f64,
+    time1: f64,
+    radius: f64,
+    material: Arc<dyn Material>,
+}
+
+impl MovingSphere {
+    pub fn new(center0: Vec3, center1: Vec3, time0: f64, time1: f64, radius: f64,
+               material: Arc<dyn Material>) -> MovingSphere {
+        MovingSphere {
+            center0,
+            center1,
+            time0,
+            time1,
+            radius,
+            material,
         }
+    }
 
-        None
+    pub fn center(&self, time: f64) -> Vec3 {
+        if self.time1 <= self.time0 {
+            return self.center0;
+        }
+
+        let fraction = ((time - self.time0) / (self.time1 - self.time0)).clamp(0., 1.);
+        self.center0 + (self.center1 - self.center0) * fraction
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        hit_sphere(self.center(ray.time()), self.radius, &*self.material, ray, t_min, t_max)
+    }
+
+    // Covers the whole path
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::surrounding_box(&sphere_box(self.center0, self.radius), &sphere_box(self.center1, self.radius)))
+    }
+}
+
+fn hit_sphere<'a>(center: Vec3, radius: f64, material: &'a dyn Material,
+                  ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'a>> {
+    let oc = ray.origin() - center;
+    let a = ray.direction().length_squared();
+    let half_b = Vec3::dot(&oc, &ray.direction());
+    let c = oc.length_squared() - radius * radius;
+    let discriminant = half_b * half_b - a * c;
+
+    if discriminant >= 0. {
+        let sqrt_discriminant = discriminant.sqrt();
+        let root_neg = (-half_b - sqrt_discriminant) / a;
+        let root_pos = (-half_b + sqrt_discriminant) / a;
+
+        for root in [root_neg, root_pos].iter() {
+            if *root < t_max && *root > t_min {
+                let point = ray.at(*root);
+                let normal = (point - center) / radius;
+                let front_face = Vec3::dot(&ray.direction(), &normal) < 0.0;
+                let (u, v) = Sphere::uv(normal);
+
+                let normal = if front_face { normal } else { -normal };
+
+                return Some(HitRecord::new(
+                    point,
+                    normal,
+                    material,
+                    *root,
+                    front_face,
+                ).with_uv(u, v));
+            }
+        }
     }
+
+    None
+}
+
+fn sphere_box(center: Vec3, radius: f64) -> Aabb {
+    let radius = Vec3::constant_new(radius.abs());
+    Aabb::new(center - radius, center + radius)
 }
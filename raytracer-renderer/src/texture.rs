@@ -0,0 +1,243 @@
+use crate::perlin::Perlin;
+use crate::vec3::Vec3;
+
+use image::ImageResult;
+use serde::{ Serialize, Deserialize };
+use std::path::Path;
+use std::sync::Arc;
+
+// Color that varies over a surface, looked up by the (u, v) surface
+// coordinates and the hit point
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, point: Vec3) -> Vec3;
+}
+
+pub struct SolidColor {
+    color: Vec3,
+}
+
+impl SolidColor {
+    pub fn new(color: Vec3) -> SolidColor {
+        SolidColor {
+            color,
+        }
+    }
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _point: Vec3) -> Vec3 {
+        self.color
+    }
+}
+
+// Alternates between two textures in 3D cells of the given size, so it does
+// not depend on the surface parameterization
+pub struct Checker {
+    even: Arc<dyn Texture>,
+    odd: Arc<dyn Texture>,
+    scale: f64,
+}
+
+impl Checker {
+    pub fn new(even: Arc<dyn Texture>, odd: Arc<dyn Texture>, scale: f64) -> Checker {
+        Checker {
+            even,
+            odd,
+            scale,
+        }
+    }
+
+    pub fn from_colors(even: Vec3, odd: Vec3, scale: f64) -> Checker {
+        Checker::new(Arc::new(SolidColor::new(even)), Arc::new(SolidColor::new(odd)), scale)
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, u: f64, v: f64, point: Vec3) -> Vec3 {
+        let cell = |x: f64| (x / self.scale).floor() as i64;
+        let sum = cell(point.x()) + cell(point.y()) + cell(point.z());
+
+        if sum.rem_euclid(2) == 0 {
+            self.even.value(u, v, point)
+        } else {
+            self.odd.value(u, v, point)
+        }
+    }
+}
+
+// What happens to uv coordinates outside [0, 1]
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterMode {
+    Nearest,
+    #[default]
+    Bilinear,
+}
+
+// Image mapped onto the surface by its uv coordinates, v = 1 is the top row
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    // Linear color
+    pixels: Vec<Vec3>,
+    wrap: WrapMode,
+    filter: FilterMode,
+}
+
+impl ImageTexture {
+    // 8-bit images are assumed to be sRGB encoded and get linearized, float
+    // images (.hdr, .exr) are used as they are
+    pub fn load<P: AsRef<Path>>(path: P) -> ImageResult<ImageTexture> {
+        let image = image::open(path)?;
+        let (width, height) = (image.width() as usize, image.height() as usize);
+
+        let pixels = match image {
+            image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_) => image
+                .into_rgb32f()
+                .pixels()
+                .map(|p| Vec3::new(p.0[0] as f64, p.0[1] as f64, p.0[2] as f64))
+                .collect(),
+            _ => image
+                .into_rgb8()
+                .pixels()
+                .map(|p| Vec3::new(srgb_to_linear(p.0[0]), srgb_to_linear(p.0[1]), srgb_to_linear(p.0[2])))
+                .collect(),
+        };
+
+        Ok(ImageTexture::new(width, height, pixels))
+    }
+
+    pub fn new(width: usize, height: usize, pixels: Vec<Vec3>) -> ImageTexture {
+        assert_eq!(pixels.len(), width * height, "image texture size does not match its pixels");
+
+        ImageTexture {
+            width,
+            height,
+            pixels,
+            wrap: WrapMode::Repeat,
+            filter: FilterMode::Bilinear,
+        }
+    }
+
+    pub fn with_wrap(mut self, wrap: WrapMode) -> ImageTexture {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: FilterMode) -> ImageTexture {
+        self.filter = filter;
+        self
+    }
+
+    fn texel(&self, x: i64, y: i64) -> Vec3 {
+        let wrap = |i: i64, size: usize| -> usize {
+            let size = size as i64;
+            let wrapped = match self.wrap {
+                WrapMode::Repeat => i.rem_euclid(size),
+                WrapMode::Clamp => i.clamp(0, size - 1),
+                WrapMode::Mirror => {
+                    let period = i.rem_euclid(2 * size);
+                    if period < size { period } else { 2 * size - 1 - period }
+                }
+            };
+            wrapped as usize
+        };
+
+        self.pixels[wrap(y, self.height) * self.width + wrap(x, self.width)]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _point: Vec3) -> Vec3 {
+        // Texel centers sit at half coordinates
+        let x = u * self.width as f64 - 0.5;
+        let y = (1. - v) * self.height as f64 - 0.5;
+
+        match self.filter {
+            FilterMode::Nearest => self.texel(x.round() as i64, y.round() as i64),
+            FilterMode::Bilinear => {
+                let (x0, y0) = (x.floor(), y.floor());
+                let (tx, ty) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+
+                let top = self.texel(x0, y0) * (1. - tx) + self.texel(x0 + 1, y0) * tx;
+                let bottom = self.texel(x0, y0 + 1) * (1. - tx) + self.texel(x0 + 1, y0 + 1) * tx;
+
+                top * (1. - ty) + bottom * ty
+            }
+        }
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f64 {
+    let c = value as f64 / 255.;
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NoiseKind {
+    // Smooth Perlin noise
+    #[default]
+    Perlin,
+    // Sum of noise octaves
+    Turbulence,
+    // Sine stripes along z perturbed by turbulence
+    Marble,
+}
+
+// Grey noise pattern over 3D space, scale is the frequency
+pub struct NoiseTexture {
+    perlin: Perlin,
+    kind: NoiseKind,
+    scale: f64,
+    color: Vec3,
+}
+
+// Octaves summed for turbulence and marble
+const TURBULENCE_DEPTH: usize = 7;
+
+impl NoiseTexture {
+    pub fn new(kind: NoiseKind, scale: f64, seed: u64) -> NoiseTexture {
+        NoiseTexture {
+            perlin: Perlin::new(seed),
+            kind,
+            scale,
+            color: Vec3::constant_new(1.),
+        }
+    }
+
+    // Tints the pattern, which is white by default
+    pub fn with_color(mut self, color: Vec3) -> NoiseTexture {
+        self.color = color;
+        self
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, point: Vec3) -> Vec3 {
+        let scaled = point * self.scale;
+
+        let intensity = match self.kind {
+            NoiseKind::Perlin => 0.5 * (1. + self.perlin.noise(scaled)),
+            NoiseKind::Turbulence => self.perlin.turbulence(scaled, TURBULENCE_DEPTH),
+            // The scale only sets the stripe frequency, the veins keep their size
+            NoiseKind::Marble => 0.5 * (1. + f64::sin(scaled.z() + 10. * self.perlin.turbulence(point, TURBULENCE_DEPTH))),
+        };
+
+        self.color * intensity
+    }
+}
@@ -0,0 +1,127 @@
+use serde::{ Serialize, Deserialize };
+
+// Rectangle of pixels, x and y of the top left corner with rows from the top
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Tile {
+    pub fn pixel_count(&self) -> usize {
+        self.width * self.height
+    }
+
+    // Index into the whole image of every pixel, row by row
+    pub fn pixel_indices(&self, image_width: usize) -> impl Iterator<Item = usize> + '_ {
+        (self.y..self.y + self.height)
+            .flat_map(move |row| (self.x..self.x + self.width).map(move |col| row * image_width + col))
+    }
+}
+
+// Order tiles are started in, each thread takes the next one when it is done
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TileOrder {
+    // Rows from the top, left to right
+    #[default]
+    Scanline,
+    // Outwards from the center, where the subject usually is
+    Spiral,
+    // Along a Hilbert curve, tiles in flight stay close to each other
+    Hilbert,
+}
+
+// Splits the image into tiles of tile_size pixels, smaller at the right and
+// bottom edges
+pub fn tiles(image_width: usize, image_height: usize, tile_size: usize, order: TileOrder) -> Vec<Tile> {
+    let columns = image_width.div_ceil(tile_size);
+    let rows = image_height.div_ceil(tile_size);
+
+    let tile = |(column, row): (usize, usize)| {
+        let (x, y) = (column * tile_size, row * tile_size);
+        Tile {
+            x,
+            y,
+            width: tile_size.min(image_width - x),
+            height: tile_size.min(image_height - y),
+        }
+    };
+
+    match order {
+        TileOrder::Scanline => (0..rows)
+            .flat_map(|row| (0..columns).map(move |column| (column, row)))
+            .map(tile)
+            .collect(),
+        TileOrder::Spiral => spiral(columns, rows).into_iter().map(tile).collect(),
+        TileOrder::Hilbert => hilbert(columns, rows).into_iter().map(tile).collect(),
+    }
+}
+
+// Walks right, down, left and up from the center with legs growing by one
+// every two turns, keeping the cells inside the grid
+fn spiral(columns: usize, rows: usize) -> Vec<(usize, usize)> {
+    let mut cells = Vec::with_capacity(columns * rows);
+    let (mut column, mut row) = ((columns as isize - 1) / 2, (rows as isize - 1) / 2);
+    let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+    let mut leg_length = 1;
+    let mut direction = 0;
+
+    while cells.len() < columns * rows {
+        for _ in 0..2 {
+            let (step_column, step_row) = directions[direction % 4];
+            for _ in 0..leg_length {
+                if (0..columns as isize).contains(&column) && (0..rows as isize).contains(&row) {
+                    cells.push((column as usize, row as usize));
+                }
+                column += step_column;
+                row += step_row;
+            }
+            direction += 1;
+        }
+        leg_length += 1;
+    }
+
+    cells
+}
+
+// Hilbert curve over the smallest power of two square covering the grid,
+// skipping the cells outside of it
+fn hilbert(columns: usize, rows: usize) -> Vec<(usize, usize)> {
+    let size = columns.max(rows).next_power_of_two();
+
+    (0..size * size)
+        .map(|distance| hilbert_cell(size, distance))
+        .filter(|&(column, row)| column < columns && row < rows)
+        .collect()
+}
+
+// Cell at distance along the curve, from Wikipedia "Hilbert curve"
+fn hilbert_cell(size: usize, distance: usize) -> (usize, usize) {
+    let (mut x, mut y) = (0, 0);
+    let mut t = distance;
+    let mut s = 1;
+
+    while s < size {
+        let rx = 1 & (t / 2);
+        let ry = 1 & (t ^ rx);
+
+        // Rotate the quadrant
+        if ry == 0 {
+            if rx == 1 {
+                x = s - 1 - x;
+                y = s - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+
+        x += s * rx;
+        y += s * ry;
+        t /= 4;
+        s *= 2;
+    }
+
+    (x, y)
+}
@@ -0,0 +1,251 @@
+use crate::framebuffer::Framebuffer;
+use crate::rng::splitmix64;
+use crate::utils::clamp;
+
+use serde::{ Serialize, Deserialize };
+
+// How radiance above 1 is brought into the displayable range
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TonemapOperator {
+    // Cuts everything above 1 off
+    #[default]
+    Clamp,
+    // L / (1 + L) on the luminance, never quite reaches white
+    Reinhard,
+    // Reinhard that maps white_point to white
+    ExtendedReinhard,
+    // Filmic curve of the ACES reference transform, fitted by Narkowicz
+    Aces,
+}
+
+// Turns the linear framebuffer into 8 or 16 bit sRGB: exposure, tone mapping,
+// the sRGB transfer function, then quantization with optional dithering
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToneMapping {
+    // In stops, +1 doubles the brightness
+    pub exposure: f64,
+    pub operator: TonemapOperator,
+    // Luminance that extended_reinhard maps to white
+    pub white_point: f64,
+    // Adds noise of about one quantization step, which hides banding in smooth
+    // gradients
+    pub dither: bool,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        ToneMapping {
+            exposure: 0.,
+            operator: TonemapOperator::default(),
+            white_point: 4.,
+            dither: false,
+        }
+    }
+}
+
+impl ToneMapping {
+    // Linear color in [0, 1] for display
+    pub fn map(&self, [r, g, b]: [f32; 3]) -> [f64; 3] {
+        let scale = f64::powf(2., self.exposure);
+        let color = [r as f64 * scale, g as f64 * scale, b as f64 * scale];
+
+        let mapped = match self.operator {
+            TonemapOperator::Clamp => color,
+            TonemapOperator::Reinhard => scale_luminance(color, |luminance| luminance / (1. + luminance)),
+            TonemapOperator::ExtendedReinhard => {
+                let white_squared = self.white_point * self.white_point;
+                scale_luminance(color, |luminance| luminance * (1. + luminance / white_squared) / (1. + luminance))
+            }
+            TonemapOperator::Aces => color.map(aces_filmic),
+        };
+
+        mapped.map(|channel| clamp(channel, 0., 1.))
+    }
+
+    // RGB8 rows from the top
+    pub fn to_rgb8(&self, framebuffer: &Framebuffer) -> Vec<u8> {
+        self.quantize(framebuffer, u8::MAX as f64).map(|value| value as u8).collect()
+    }
+
+    // RGB16 rows from the top, with 256 times the steps of to_rgb8
+    pub fn to_rgb16(&self, framebuffer: &Framebuffer) -> Vec<u16> {
+        self.quantize(framebuffer, u16::MAX as f64).map(|value| value as u16).collect()
+    }
+
+    // Channels encoded and rounded to integers in [0, max]
+    fn quantize<'a>(&'a self, framebuffer: &'a Framebuffer, max: f64) -> impl Iterator<Item = f64> + 'a {
+        framebuffer
+            .pixels()
+            .iter()
+            .enumerate()
+            .flat_map(move |(i, &color)| {
+                let mapped = self.map(color);
+
+                (0..3).map(move |channel| {
+                    let dither = if self.dither { triangular_noise(i, channel) } else { 0. };
+                    let encoded = srgb_oetf(mapped[channel]) * max + dither;
+                    clamp(encoded.round(), 0., max)
+                })
+            })
+    }
+}
+
+// Rec. 709 weights
+fn luminance([r, g, b]: [f64; 3]) -> f64 {
+    0.2126 * r + 0.7152 * g + 0.0722 * b
+}
+
+// Maps the luminance and scales the channels along, which keeps the hue
+fn scale_luminance(color: [f64; 3], curve: impl Fn(f64) -> f64) -> [f64; 3] {
+    let luminance = luminance(color);
+    if luminance <= 0. {
+        return [0.; 3];
+    }
+
+    let scale = curve(luminance) / luminance;
+    color.map(|channel| channel * scale)
+}
+
+fn aces_filmic(x: f64) -> f64 {
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+// Linear to sRGB encoded, both in [0, 1]
+fn srgb_oetf(linear: f64) -> f64 {
+    if linear <= 0.0031308 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1. / 2.4) - 0.055
+    }
+}
+
+// sRGB encoded to linear, for values meant to end up in the file as they are
+pub(crate) fn srgb_eotf(encoded: f64) -> f64 {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// In (-1, 1), denser around 0. A fixed function of the pixel, so the same
+// render dithers the same way every time.
+fn triangular_noise(pixel: usize, channel: usize) -> f64 {
+    let hash = splitmix64((pixel as u64) << 2 | channel as u64);
+    let unit = |bits: u64| (bits >> 11) as f64 * (1. / (1u64 << 53) as f64);
+
+    unit(hash) + unit(splitmix64(hash)) - 1.
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::vec3::Vec3;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    fn operator(operator: TonemapOperator) -> ToneMapping {
+        ToneMapping { operator, ..ToneMapping::default() }
+    }
+
+    #[test]
+    fn clamp_cuts_off_above_one() {
+        let clamp = operator(TonemapOperator::Clamp);
+
+        assert_eq!(clamp.map([0.25, 1., 7.]), [0.25, 1., 1.]);
+        assert_eq!(clamp.map([-1., 0., 0.5]), [0., 0., 0.5]);
+    }
+
+    #[test]
+    fn reinhard_maps_the_luminance_and_keeps_the_hue() {
+        let reinhard = operator(TonemapOperator::Reinhard);
+
+        let [r, g, b] = reinhard.map([1., 1., 1.]);
+        assert!(close(r, 0.5) && close(g, 0.5) && close(b, 0.5));
+
+        // Luminance 0.5 becomes 1/3
+        let [r, g, b] = reinhard.map([0., (0.5 / 0.7152) as f32, 0.]);
+        assert!(r == 0. && close(luminance([r, g, b]), 1. / 3.) && b == 0.);
+
+        // The channels keep their ratios
+        let [r, g, b] = reinhard.map([0.8, 0.4, 0.2]);
+        assert!(close(r, 2. * g) && close(g, 2. * b) && r < 0.8);
+
+        assert!(reinhard.map([1000.; 3]).iter().all(|&channel| channel < 1.));
+    }
+
+    #[test]
+    fn extended_reinhard_reaches_white_at_the_white_point() {
+        let mut extended = operator(TonemapOperator::ExtendedReinhard);
+        extended.white_point = 6.;
+
+        let [r, g, b] = extended.map([6.; 3]);
+        assert!(close(r, 1.) && close(g, 1.) && close(b, 1.));
+
+        let [below, ..] = extended.map([3.; 3]);
+        assert!(below < 1.);
+        assert!(below > operator(TonemapOperator::Reinhard).map([3.; 3])[0]);
+    }
+
+    #[test]
+    fn aces_follows_the_fitted_curve() {
+        let aces = operator(TonemapOperator::Aces);
+
+        assert_eq!(aces.map([0.; 3]), [0.; 3]);
+        let [r, ..] = aces.map([1., 0., 0.]);
+        assert!(close(r, 2.54 / 3.16));
+        assert_eq!(aces.map([100.; 3]), [1.; 3]);
+
+        let mapped = [0.1, 0.2, 0.4, 0.8].map(|x| aces.map([x, 0., 0.])[0]);
+        assert!(mapped.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn exposure_is_in_stops() {
+        let exposure = |exposure| ToneMapping { exposure, ..ToneMapping::default() };
+
+        assert_eq!(exposure(1.).map([0.25, 0.125, 0.]), [0.5, 0.25, 0.]);
+        assert_eq!(exposure(-2.).map([0.75, 0.375, 2.]), [0.1875, 0.09375, 0.5]);
+    }
+
+    #[test]
+    fn srgb_is_linear_below_the_breakpoint() {
+        assert_eq!(srgb_oetf(0.), 0.);
+        assert!(close(srgb_oetf(0.0031308), 0.04045));
+        assert!(close(srgb_oetf(1.), 1.));
+        assert!(close(srgb_oetf(0.18), 0.461356));
+
+        // Both pieces meet at the breakpoint
+        assert!(close(srgb_oetf(0.0031308 + 1e-9), srgb_oetf(0.0031308)));
+        for encoded in [0., 0.02, 0.04045, 0.1, 0.5, 1.] {
+            assert!(close(srgb_oetf(srgb_eotf(encoded)), encoded));
+        }
+    }
+
+    #[test]
+    fn quantization_rounds_to_the_nearest_step() {
+        let framebuffer = Framebuffer::from_colors(2, 1, [Vec3::new(0., 1., 0.5), Vec3::new(2., -1., 0.0031308)].into_iter());
+        let tone_mapping = ToneMapping::default();
+
+        assert_eq!(tone_mapping.to_rgb8(&framebuffer), [0, 255, 188, 255, 0, 10]);
+        assert_eq!(tone_mapping.to_rgb16(&framebuffer)[..3], [0, 65535, 48192]);
+    }
+
+    #[test]
+    fn dither_stays_within_one_step() {
+        let (width, height) = (64, 64);
+        let colors = (0..width * height).map(|i| Vec3::new(i as f64 / (width * height) as f64, 0.5, 0.));
+        let framebuffer = Framebuffer::from_colors(width, height, colors);
+        let plain = ToneMapping::default().to_rgb8(&framebuffer);
+        let dithered = ToneMapping { dither: true, ..ToneMapping::default() }.to_rgb8(&framebuffer);
+
+        assert!(plain.iter().zip(&dithered).all(|(&plain, &dithered)| plain.abs_diff(dithered) <= 1));
+        assert!(plain != dithered);
+        assert_eq!(dithered, ToneMapping { dither: true, ..ToneMapping::default() }.to_rgb8(&framebuffer));
+    }
+}
@@ -0,0 +1,223 @@
+use crate::vec3::Vec3;
+use crate::aabb::Aabb;
+
+use std::ops::Mul;
+
+type Matrix = [[f64; 4]; 4];
+
+// Affine transformation as a 4x4 matrix, the inverse is kept alongside so it
+// never has to be computed from a general matrix. Compose with *, a * b applies
+// b first and then a.
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    matrix: Matrix,
+    inverse: Matrix,
+}
+
+impl Transform {
+    pub fn identity() -> Transform {
+        Transform {
+            matrix: IDENTITY,
+            inverse: IDENTITY,
+        }
+    }
+
+    pub fn translation(offset: Vec3) -> Transform {
+        let matrix_with = |offset: Vec3| {
+            let mut matrix = IDENTITY;
+            for axis in 0..3 {
+                matrix[axis][3] = offset[axis];
+            }
+            matrix
+        };
+
+        Transform {
+            matrix: matrix_with(offset),
+            inverse: matrix_with(-offset),
+        }
+    }
+
+    // Non-uniform scale along the axes, factors must not be zero
+    pub fn scaling(factors: Vec3) -> Transform {
+        let mut matrix = IDENTITY;
+        let mut inverse = IDENTITY;
+        for axis in 0..3 {
+            matrix[axis][axis] = factors[axis];
+            inverse[axis][axis] = 1. / factors[axis];
+        }
+
+        Transform {
+            matrix,
+            inverse,
+        }
+    }
+
+    // Counterclockwise rotation around axis (looking down at it from its
+    // tip) through the origin
+    pub fn rotation(axis: Vec3, degrees: f64) -> Transform {
+        let axis = axis.unit_vector();
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let (x, y, z) = (axis.x(), axis.y(), axis.z());
+        let t = 1. - cos;
+
+        // Rodrigues' rotation formula
+        let mut matrix = IDENTITY;
+        matrix[0][..3].copy_from_slice(&[t * x * x + cos, t * x * y - sin * z, t * x * z + sin * y]);
+        matrix[1][..3].copy_from_slice(&[t * x * y + sin * z, t * y * y + cos, t * y * z - sin * x]);
+        matrix[2][..3].copy_from_slice(&[t * x * z - sin * y, t * y * z + sin * x, t * z * z + cos]);
+
+        // Rotations are orthogonal, the inverse is the transpose
+        Transform {
+            matrix,
+            inverse: transpose(&matrix),
+        }
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Transform {
+            matrix: self.inverse,
+            inverse: self.matrix,
+        }
+    }
+
+    pub fn apply_point(&self, point: Vec3) -> Vec3 {
+        apply(&self.matrix, point, 1.)
+    }
+
+    pub fn apply_vector(&self, vector: Vec3) -> Vec3 {
+        apply(&self.matrix, vector, 0.)
+    }
+
+    // Normals go through the inverse transpose to stay perpendicular to the
+    // surface under non-uniform scale. The result is not normalized.
+    pub fn apply_normal(&self, normal: Vec3) -> Vec3 {
+        apply(&transpose(&self.inverse), normal, 0.)
+    }
+
+    // Box around the eight transformed corners
+    pub fn apply_box(&self, aabb: &Aabb) -> Aabb {
+        let (minimum, maximum) = (aabb.minimum(), aabb.maximum());
+
+        (0..8)
+            .map(|corner| Vec3::new(
+                if corner & 1 == 0 { minimum.x() } else { maximum.x() },
+                if corner & 2 == 0 { minimum.y() } else { maximum.y() },
+                if corner & 4 == 0 { minimum.z() } else { maximum.z() },
+            ))
+            .map(|corner| self.apply_point(corner))
+            .fold(Aabb::empty(), |aabb, point| Aabb::surrounding_box(&aabb, &Aabb::new(point, point)))
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::identity()
+    }
+}
+
+impl Mul<Transform> for Transform {
+    type Output = Transform;
+
+    fn mul(self, other: Transform) -> Transform {
+        Transform {
+            matrix: multiply(&self.matrix, &other.matrix),
+            inverse: multiply(&other.inverse, &self.inverse),
+        }
+    }
+}
+
+const IDENTITY: Matrix = [
+    [1., 0., 0., 0.],
+    [0., 1., 0., 0.],
+    [0., 0., 1., 0.],
+    [0., 0., 0., 1.],
+];
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let mut product = [[0.; 4]; 4];
+    for (row, product_row) in product.iter_mut().enumerate() {
+        for (column, value) in product_row.iter_mut().enumerate() {
+            *value = (0..4).map(|i| a[row][i] * b[i][column]).sum();
+        }
+    }
+    product
+}
+
+fn transpose(matrix: &Matrix) -> Matrix {
+    let mut transposed = [[0.; 4]; 4];
+    for (row, transposed_row) in transposed.iter_mut().enumerate() {
+        for (column, value) in transposed_row.iter_mut().enumerate() {
+            *value = matrix[column][row];
+        }
+    }
+    transposed
+}
+
+// w is 1 for points and 0 for directions, the bottom row of an affine matrix
+// never changes it
+fn apply(matrix: &Matrix, v: Vec3, w: f64) -> Vec3 {
+    let row = |i: usize| matrix[i][0] * v.x() + matrix[i][1] * v.y() + matrix[i][2] * v.z() + matrix[i][3] * w;
+    Vec3::new(row(0), row(1), row(2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Vec3, expected: Vec3) {
+        assert!((actual - expected).length() < 1e-9, "{:?} is not {:?}", actual, expected);
+    }
+
+    fn example() -> Transform {
+        Transform::translation(Vec3::new(1., -2., 3.))
+            * Transform::rotation(Vec3::new(1., 1., 0.), 30.)
+            * Transform::scaling(Vec3::new(2., 0.5, -3.))
+    }
+
+    #[test]
+    fn inverse_undoes_the_transform() {
+        let transform = example();
+        let point = Vec3::new(0.3, -1.7, 2.2);
+
+        assert_close(transform.inverse().apply_point(transform.apply_point(point)), point);
+        assert_close(transform.apply_point(transform.inverse().apply_point(point)), point);
+        assert_close((transform * transform.inverse()).apply_vector(point), point);
+    }
+
+    #[test]
+    fn composition_applies_the_right_side_first() {
+        let scale = Transform::scaling(Vec3::new(2., 3., 4.));
+        let translate = Transform::translation(Vec3::new(1., 0., 0.));
+        let point = Vec3::new(1., 1., 1.);
+
+        assert_close((translate * scale).apply_point(point), Vec3::new(3., 3., 4.));
+        assert_close((scale * translate).apply_point(point), Vec3::new(4., 3., 4.));
+        // Directions ignore the translation
+        assert_close((translate * scale).apply_vector(point), Vec3::new(2., 3., 4.));
+    }
+
+    #[test]
+    fn rotation_is_counterclockwise() {
+        let rotation = Transform::rotation(Vec3::new(0., 0., 1.), 90.);
+        assert_close(rotation.apply_vector(Vec3::new(1., 0., 0.)), Vec3::new(0., 1., 0.));
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_under_non_uniform_scale() {
+        let transform = example();
+        let (tangent, normal) = (Vec3::new(1., -1., 0.), Vec3::new(1., 1., 0.));
+
+        let dot = Vec3::dot(&transform.apply_vector(tangent), &transform.apply_normal(normal));
+        assert!(dot.abs() < 1e-9, "{}", dot);
+    }
+
+    #[test]
+    fn boxes_bound_the_rotated_corners() {
+        let aabb = Aabb::new(Vec3::constant_new(-1.), Vec3::constant_new(1.));
+        let rotated = Transform::rotation(Vec3::new(0., 1., 0.), 45.).apply_box(&aabb);
+
+        let half_diagonal = 2f64.sqrt();
+        assert_close(rotated.minimum(), Vec3::new(-half_diagonal, -1., -half_diagonal));
+        assert_close(rotated.maximum(), Vec3::new(half_diagonal, 1., half_diagonal));
+    }
+}
@@ -0,0 +1,326 @@
+use crate::hittable::{ Hittable, HitRecord };
+use crate::vec3::Vec3;
+use crate::ray::Ray;
+use crate::material::Material;
+use crate::aabb::Aabb;
+use crate::bvh::Bvh;
+
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+// Determinants below this are treated as a ray parallel to the triangle
+const PARALLEL_EPSILON: f64 = 1e-12;
+
+pub struct Triangle {
+    vertices: [Vec3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[(f64, f64); 3]>,
+    material: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(vertices: [Vec3; 3], material: Arc<dyn Material>) -> Triangle {
+        Triangle {
+            vertices,
+            normals: None,
+            uvs: None,
+            material,
+        }
+    }
+
+    // Per-vertex shading normals, interpolated across the face
+    pub fn with_normals(mut self, normals: [Vec3; 3]) -> Triangle {
+        self.normals = Some(normals);
+        self
+    }
+
+    pub fn with_uvs(mut self, uvs: [(f64, f64); 3]) -> Triangle {
+        self.uvs = Some(uvs);
+        self
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t, b1, b2) = intersect(ray, &self.vertices, t_min, t_max)?;
+        Some(hit_record(ray, t, b1, b2, &self.vertices, self.normals.as_ref(), self.uvs.as_ref(), &*self.material))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(triangle_box(&self.vertices))
+    }
+}
+
+// A face of a mesh refers to a vertex past the end of the vertex buffer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndexOutOfRange {
+    pub face: usize,
+    pub index: usize,
+    pub vertex_count: usize,
+}
+
+impl fmt::Display for IndexOutOfRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "triangle {} uses vertex {} but there are only {}", self.face, self.index, self.vertex_count)
+    }
+}
+
+impl Error for IndexOutOfRange {}
+
+// Triangles sharing one vertex buffer, index buffer and material. The mesh is
+// a single object in the World and keeps its own BVH over the triangles.
+pub struct TriangleMesh {
+    positions: Vec<Vec3>,
+    normals: Option<Vec<Vec3>>,
+    uvs: Option<Vec<(f64, f64)>>,
+    indices: Vec<[usize; 3]>,
+    material: Arc<dyn Material>,
+    bvh: Bvh,
+}
+
+impl TriangleMesh {
+    pub fn new(positions: Vec<Vec3>, indices: Vec<[usize; 3]>, material: Arc<dyn Material>)
+               -> Result<TriangleMesh, IndexOutOfRange> {
+        for (face, vertices) in indices.iter().enumerate() {
+            if let Some(&index) = vertices.iter().find(|&&index| index >= positions.len()) {
+                return Err(IndexOutOfRange { face, index, vertex_count: positions.len() });
+            }
+        }
+
+        let boxes: Vec<Aabb> = indices
+            .iter()
+            .map(|face| triangle_box(&face.map(|i| positions[i])))
+            .collect();
+
+        Ok(TriangleMesh {
+            bvh: Bvh::build(&boxes),
+            positions,
+            normals: None,
+            uvs: None,
+            indices,
+            material,
+        })
+    }
+
+    // Per-vertex normals, indexed like the positions
+    pub fn with_normals(mut self, normals: Vec<Vec3>) -> TriangleMesh {
+        assert_eq!(normals.len(), self.positions.len(), "normal buffer must match the vertex buffer");
+        self.normals = Some(normals);
+        self
+    }
+
+    // Per-vertex texture coordinates, indexed like the positions
+    pub fn with_uvs(mut self, uvs: Vec<(f64, f64)>) -> TriangleMesh {
+        assert_eq!(uvs.len(), self.positions.len(), "uv buffer must match the vertex buffer");
+        self.uvs = Some(uvs);
+        self
+    }
+
+    pub fn with_material(mut self, material: Arc<dyn Material>) -> TriangleMesh {
+        self.material = material;
+        self
+    }
+
+    pub fn material(&self) -> &Arc<dyn Material> {
+        &self.material
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len()
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.bvh.hit(ray, t_min, t_max, |i, closest_so_far| {
+            let face = self.indices[i];
+            let vertices = face.map(|i| self.positions[i]);
+            let (t, b1, b2) = intersect(ray, &vertices, t_min, closest_so_far)?;
+
+            let normals = self.normals.as_ref().map(|normals| face.map(|i| normals[i]));
+            let uvs = self.uvs.as_ref().map(|uvs| face.map(|i| uvs[i]));
+
+            Some(hit_record(ray, t, b1, b2, &vertices, normals.as_ref(), uvs.as_ref(), &*self.material))
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.bvh.bounding_box()
+    }
+}
+
+fn triangle_box(vertices: &[Vec3; 3]) -> Aabb {
+    Aabb::new(
+        Vec3::min(&Vec3::min(&vertices[0], &vertices[1]), &vertices[2]),
+        Vec3::max(&Vec3::max(&vertices[0], &vertices[1]), &vertices[2]),
+    )
+}
+
+// Möller–Trumbore, returns t and the barycentric weights of the second and
+// third vertex
+fn intersect(ray: &Ray, vertices: &[Vec3; 3], t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+    let edge1 = vertices[1] - vertices[0];
+    let edge2 = vertices[2] - vertices[0];
+
+    let p = Vec3::cross(&ray.direction(), &edge2);
+    let determinant = Vec3::dot(&edge1, &p);
+    if determinant.abs() < PARALLEL_EPSILON {
+        return None;
+    }
+    let inv_determinant = 1. / determinant;
+
+    let s = ray.origin() - vertices[0];
+    let b1 = Vec3::dot(&s, &p) * inv_determinant;
+    if !(0. ..=1.).contains(&b1) {
+        return None;
+    }
+
+    let q = Vec3::cross(&s, &edge1);
+    let b2 = Vec3::dot(&ray.direction(), &q) * inv_determinant;
+    if b2 < 0. || b1 + b2 > 1. {
+        return None;
+    }
+
+    let t = Vec3::dot(&edge2, &q) * inv_determinant;
+    if t < t_max && t > t_min {
+        Some((t, b1, b2))
+    } else {
+        None
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn hit_record<'a>(ray: &Ray, t: f64, b1: f64, b2: f64, vertices: &[Vec3; 3], normals: Option<&[Vec3; 3]>,
+                  uvs: Option<&[(f64, f64); 3]>, material: &'a dyn Material) -> HitRecord<'a> {
+    let b0 = 1. - b1 - b2;
+
+    let geometric_normal = Vec3::cross(&(vertices[1] - vertices[0]), &(vertices[2] - vertices[0])).unit_vector();
+    let front_face = Vec3::dot(&ray.direction(), &geometric_normal) < 0.0;
+
+    let normal = match normals {
+        Some(n) => {
+            // Keep the shading normal on the same side as the geometry
+            let shading_normal = (n[0] * b0 + n[1] * b1 + n[2] * b2).unit_vector();
+            if Vec3::dot(&shading_normal, &geometric_normal) < 0. { -shading_normal } else { shading_normal }
+        }
+        None => geometric_normal,
+    };
+    let normal = if front_face { normal } else { -normal };
+
+    let (u, v) = match uvs {
+        Some(uv) => (
+            uv[0].0 * b0 + uv[1].0 * b1 + uv[2].0 * b2,
+            uv[0].1 * b0 + uv[1].1 * b1 + uv[2].1 * b2,
+        ),
+        None => (b1, b2),
+    };
+
+    HitRecord::new(ray.at(t), normal, material, t, front_face).with_uv(u, v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::material::Lambertian;
+    use crate::rng::Pcg32;
+    use crate::world::World;
+
+    fn material() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(Vec3::constant_new(0.5)))
+    }
+
+    // In the z = 0 plane, facing +z
+    fn unit_triangle() -> Triangle {
+        Triangle::new([Vec3::new(0., 0., 0.), Vec3::new(1., 0., 0.), Vec3::new(0., 1., 0.)], material())
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "{} is not {}", actual, expected);
+    }
+
+    #[test]
+    fn hits_front_face() {
+        let ray = Ray::new(Vec3::new(0.25, 0.5, 2.), Vec3::new(0., 0., -1.));
+        let triangle = unit_triangle();
+        let hit_record = triangle.hit(&ray, 0.001, f64::INFINITY).expect("ray points at the triangle");
+
+        assert_close(hit_record.t(), 2.);
+        assert_close(hit_record.point().x(), 0.25);
+        assert_close(hit_record.point().y(), 0.5);
+        assert_close(hit_record.normal().z(), 1.);
+        assert!(hit_record.front_face());
+        // Barycentric weights of the second and third vertex without uvs
+        assert_close(hit_record.u(), 0.25);
+        assert_close(hit_record.v(), 0.5);
+    }
+
+    #[test]
+    fn hits_back_face_with_flipped_normal() {
+        let ray = Ray::new(Vec3::new(0.25, 0.25, -1.), Vec3::new(0., 0., 1.));
+        let triangle = unit_triangle();
+        let hit_record = triangle.hit(&ray, 0.001, f64::INFINITY).expect("ray points at the triangle");
+
+        assert_close(hit_record.t(), 1.);
+        assert_close(hit_record.normal().z(), -1.);
+        assert!(!hit_record.front_face());
+    }
+
+    #[test]
+    fn misses_outside_edges_parallel_rays_and_t_range() {
+        let triangle = unit_triangle();
+        let down = Vec3::new(0., 0., -1.);
+
+        assert!(triangle.hit(&Ray::new(Vec3::new(0.6, 0.6, 1.), down), 0.001, f64::INFINITY).is_none());
+        assert!(triangle.hit(&Ray::new(Vec3::new(-0.1, 0.5, 1.), down), 0.001, f64::INFINITY).is_none());
+        assert!(triangle.hit(&Ray::new(Vec3::new(0.5, -0.1, 1.), down), 0.001, f64::INFINITY).is_none());
+        assert!(triangle.hit(&Ray::new(Vec3::new(-1., 0.2, 0.), Vec3::new(1., 0., 0.)), 0.001, f64::INFINITY).is_none());
+        assert!(triangle.hit(&Ray::new(Vec3::new(0.2, 0.2, 1.), down), 0.001, 0.5).is_none());
+        assert!(triangle.hit(&Ray::new(Vec3::new(0.2, 0.2, 1.), -down), 0.001, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn interpolates_shading_normals_and_uvs() {
+        let normal = Vec3::new(1., 0., 1.).unit_vector();
+        let triangle = unit_triangle()
+            .with_normals([normal; 3])
+            .with_uvs([(0., 0.), (1., 0.), (1., 1.)]);
+        let ray = Ray::new(Vec3::new(0.5, 0.25, 1.), Vec3::new(0., 0., -1.));
+        let hit_record = triangle.hit(&ray, 0.001, f64::INFINITY).expect("ray points at the triangle");
+
+        assert_close(hit_record.normal().x(), normal.x());
+        assert_close(hit_record.normal().z(), normal.z());
+        assert_close(hit_record.u(), 0.75);
+        assert_close(hit_record.v(), 0.25);
+    }
+
+    #[test]
+    fn mesh_rejects_indices_past_the_vertex_buffer() {
+        let positions = vec![Vec3::constant_new(0.), Vec3::new(1., 0., 0.), Vec3::new(0., 1., 0.)];
+        let error = TriangleMesh::new(positions, vec![[0, 1, 2], [0, 2, 3]], material()).err();
+
+        assert_eq!(error, Some(IndexOutOfRange { face: 1, index: 3, vertex_count: 3 }));
+    }
+
+    #[test]
+    fn mesh_matches_separate_triangles() {
+        let mut rng = Pcg32::new(3, 0);
+        let mut random_point = |scale: f64| Vec3::new(rng.next_f64() - 0.5, rng.next_f64() - 0.5, rng.next_f64() - 0.5) * scale;
+
+        let positions = (0..300).map(|_| random_point(10.)).collect::<Vec<_>>();
+        let indices = (0..100).map(|i| [3 * i, 3 * i + 1, 3 * i + 2]).collect::<Vec<_>>();
+        let mesh = TriangleMesh::new(positions.clone(), indices.clone(), material()).expect("indices are in range");
+
+        let mut triangles = World::new();
+        for face in &indices {
+            triangles.add(Box::new(Triangle::new(face.map(|i| positions[i]), material())));
+        }
+
+        for _ in 0..1000 {
+            let ray = Ray::new(random_point(20.), random_point(1.));
+            let expected = triangles.did_hit(&ray, 0.001, f64::INFINITY).map(|hit_record| hit_record.t());
+            let actual = mesh.hit(&ray, 0.001, f64::INFINITY).map(|hit_record| hit_record.t());
+            assert_eq!(expected, actual);
+        }
+    }
+}
@@ -1,7 +1,9 @@
-use crate::utils::{ random_double, random_double_range };
-use std::ops::{Add, AddAssign, Sub, SubAssign, Div, DivAssign, Mul, MulAssign, Neg};
+use crate::sampler::Sampler;
+use std::ops::{Add, AddAssign, Sub, SubAssign, Div, DivAssign, Mul, MulAssign, Neg, Index};
 use core::fmt;
 use std::iter::Sum;
+use std::f64::consts::PI;
+use serde::{ Serialize, Serializer, Deserialize, Deserializer };
 
 #[derive(Clone, Copy)]
 pub struct Vec3 {
@@ -12,7 +14,7 @@ pub struct Vec3 {
 
 impl fmt::Debug for Vec3 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} {} {}\n", 
+        writeln!(f, "{} {} {}", 
                (f64::sqrt(self.x) * 255.999) as i64, 
                (f64::sqrt(self.y) * 255.999) as i64, 
                (f64::sqrt(self.z) * 255.999) as i64)
@@ -20,48 +22,41 @@ impl fmt::Debug for Vec3 {
 }
 
 impl Vec3 {
-    pub fn random() -> Vec3 {
+    pub fn random(sampler: &mut dyn Sampler) -> Vec3 {
         Vec3 {
-            x: random_double(),
-            y: random_double(),
-            z: random_double(),
+            x: sampler.get_1d(),
+            y: sampler.get_1d(),
+            z: sampler.get_1d(),
         }
     }
 
-    pub fn random_range(min: f64, max: f64) -> Vec3 {
-        Vec3 {
-            x: random_double_range(min, max),
-            y: random_double_range(min, max),
-            z: random_double_range(min, max),
-        }
+    pub fn random_range(sampler: &mut dyn Sampler, min: f64, max: f64) -> Vec3 {
+        Vec3::random(sampler) * (max - min) + min
     }
 
-    pub fn random_in_unit_sphere() -> Vec3 {
-        loop {
-            let p = Vec3::random_range(-1., 1.);
-            if p.length_squared() >= 1. { continue; }
-            return p;
-        }
+    // Uniform in the unit ball, a direction and then a distance
+    pub fn random_in_unit_sphere(sampler: &mut dyn Sampler) -> Vec3 {
+        let direction = Vec3::random_unit_vector(sampler);
+        direction * sampler.get_1d().cbrt()
     }
 
-    pub fn random_in_unit_disk() -> Vec3 {
-        loop {
-            let mut p = Vec3::random_range(-1., 1.);
-            p.z = 0.;
-            if p.length_squared() >= 1. { continue; }
-            return p;
-        }
+    // Uniform in the unit disk in the xy plane
+    pub fn random_in_unit_disk(sampler: &mut dyn Sampler) -> Vec3 {
+        let (u1, u2) = sampler.get_2d();
+        let (radius, angle) = (u1.sqrt(), 2. * PI * u2);
+        Vec3::new(radius * angle.cos(), radius * angle.sin(), 0.)
     }
 
-    pub fn random_unit_vector() -> Vec3 {
-        Vec3::random_in_unit_sphere().unit_vector()
+    // Uniform on the unit sphere, warped from a single 2D sample so it keeps
+    // the sampler's stratification
+    pub fn random_unit_vector(sampler: &mut dyn Sampler) -> Vec3 {
+        let (u1, u2) = sampler.get_2d();
+        let z = 1. - 2. * u1;
+        let radius = f64::sqrt(f64::max(0., 1. - z * z));
+        let angle = 2. * PI * u2;
+        Vec3::new(radius * angle.cos(), radius * angle.sin(), z)
     }
 
-    pub fn rgb(&self) -> Vec<u8> {
-        vec![(f64::sqrt(self.x()) * 255.999) as u8,
-             (f64::sqrt(self.y()) * 255.999) as u8,
-             (f64::sqrt(self.z()) * 255.999) as u8]
-    }
 }
 
 impl Vec3 {
@@ -77,6 +72,24 @@ impl Vec3 {
         }
     }
 
+    // Component-wise minimum
+    pub fn min(u: &Vec3, v: &Vec3) -> Vec3 {
+        Vec3 {
+            x: f64::min(u.x(), v.x()),
+            y: f64::min(u.y(), v.y()),
+            z: f64::min(u.z(), v.z()),
+        }
+    }
+
+    // Component-wise maximum
+    pub fn max(u: &Vec3, v: &Vec3) -> Vec3 {
+        Vec3 {
+            x: f64::max(u.x(), v.x()),
+            y: f64::max(u.y(), v.y()),
+            z: f64::max(u.z(), v.z()),
+        }
+    }
+
     pub fn reflect(v: Vec3, n: Vec3) -> Vec3 {
         v - n * 2. * Vec3::dot(&v , &n)
     }
@@ -266,6 +279,19 @@ impl Neg for Vec3 {
     }
 }
 
+impl Index<usize> for Vec3 {
+    type Output = f64;
+
+    fn index(&self, axis: usize) -> &f64 {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 index out of range: {}", axis),
+        }
+    }
+}
+
 impl Sum for Vec3 {
     fn sum<I>(iter: I) -> Self 
          where
@@ -274,3 +300,17 @@ impl Sum for Vec3 {
         iter.fold(Vec3::constant_new(0.0), |a, b| a + b)
     }
 }
+
+// Stored as a [x, y, z] array in scene files
+impl Serialize for Vec3 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        [self.x, self.y, self.z].serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Vec3 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Vec3, D::Error> {
+        let [x, y, z] = <[f64; 3]>::deserialize(deserializer)?;
+        Ok(Vec3::new(x, y, z))
+    }
+}
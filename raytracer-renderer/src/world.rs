@@ -1,38 +1,133 @@
 use crate::hittable::{Hittable, HitRecord};
 use crate::ray::Ray;
+use crate::bvh::Bvh;
+use crate::aabb::Aabb;
+use crate::material::Material;
+
+use std::collections::HashMap;
+use std::sync::Arc;
 
 pub struct World<'a> {
     objects: Vec<Box<dyn Hittable + 'a>>,
+    // Of every object, from 1 in the order they were added
+    object_ids: Vec<usize>,
+    // By the address of the material
+    material_ids: HashMap<usize, usize>,
+    // Built by build_bvh over objects[..bounded_count], dropped on any change
+    bvh: Option<Bvh>,
+    bounded_count: usize,
+}
+
+impl<'a> Default for World<'a> {
+    fn default() -> Self {
+        World::new()
+    }
 }
 
 impl<'a> World<'a>
 {
     pub fn new() -> World<'a> {
         World {
-            objects: vec![]
+            objects: vec![],
+            object_ids: vec![],
+            material_ids: HashMap::new(),
+            bvh: None,
+            bounded_count: 0,
         }
     }
 
     pub fn clear(&mut self) {
         self.objects.clear();
+        self.object_ids.clear();
+        self.material_ids.clear();
+        self.bvh = None;
+        self.bounded_count = 0;
     }
 
     pub fn add(&mut self, object: Box<dyn Hittable + 'a>) {
         self.objects.push(object);
+        self.object_ids.push(self.object_ids.len() + 1);
+        self.bvh = None;
+    }
+
+    // Gives the material the next material id, from 1
+    pub fn register_material(&mut self, material: &Arc<dyn Material>) -> usize {
+        let next_id = self.material_ids.len() + 1;
+        *self.material_ids.entry(Arc::as_ptr(material) as *const () as usize).or_insert(next_id)
+    }
+
+    // 0 for materials that were never registered
+    pub fn material_id(&self, material: &dyn Material) -> usize {
+        let address = material as *const dyn Material as *const () as usize;
+        self.material_ids.get(&address).copied().unwrap_or(0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    // Compiles the objects into a BVH, call once the scene is complete and
+    // before rendering. Objects without a bounding box are tested linearly.
+    pub fn build_bvh(&mut self) {
+        let (bounded, unbounded): (Vec<_>, Vec<_>) = self.objects
+            .drain(..)
+            .zip(self.object_ids.drain(..))
+            .partition(|(object, _)| object.bounding_box().is_some());
+
+        self.bounded_count = bounded.len();
+        (self.objects, self.object_ids) = bounded.into_iter().chain(unbounded).unzip();
+
+        let boxes: Vec<_> = self.objects[..self.bounded_count]
+            .iter()
+            .filter_map(|object| object.bounding_box())
+            .collect();
+
+        self.bvh = Some(Bvh::build(&boxes));
     }
 
-    pub fn did_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let mut track_hit_record = None;
-        let mut closest_so_far = t_max;
+    pub fn did_hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        match &self.bvh {
+            Some(bvh) => {
+                let bvh_hit = bvh.hit(ray, t_min, t_max, |i, closest_so_far| {
+                    self.objects[i].hit(ray, t_min, closest_so_far).map(|hit_record| hit_record.with_object_id(self.object_ids[i]))
+                });
 
-        for object in &self.objects {
+                Self::closest_hit(&self.objects[self.bounded_count..], &self.object_ids[self.bounded_count..],
+                                  ray, t_min, t_max, bvh_hit)
+            }
+            None => Self::closest_hit(&self.objects, &self.object_ids, ray, t_min, t_max, None),
+        }
+    }
+
+    fn closest_hit<'b>(objects: &'b [Box<dyn Hittable + 'a>], object_ids: &[usize], ray: &Ray, t_min: f64, t_max: f64,
+                       mut track_hit_record: Option<HitRecord<'b>>) -> Option<HitRecord<'b>> {
+        let mut closest_so_far = track_hit_record.as_ref().map_or(t_max, |hit_record| hit_record.t());
+
+        for (object, &object_id) in objects.iter().zip(object_ids) {
             if let Some(hit_record) = object.hit(ray, t_min, closest_so_far) {
                 closest_so_far = hit_record.t();
-                track_hit_record = Some(hit_record);
+                track_hit_record = Some(hit_record.with_object_id(object_id));
             }
         }
 
         track_hit_record
     }
+}
+
+// Lets a World be used as a single object, e.g. shared between Instances
+impl<'a> Hittable for World<'a> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.did_hit(ray, t_min, t_max)
+    }
 
+    // Unbounded if any of the objects is
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.objects
+            .iter()
+            .try_fold(Aabb::empty(), |aabb, object| Some(Aabb::surrounding_box(&aabb, &object.bounding_box()?)))
+    }
 }
@@ -13,19 +13,19 @@ pub struct Broadcaster {
     inner: Mutex<BroadcasterInner>,
 }
 
+type Channel = (Sender<Result<Bytes, Error>>, Receiver<Result<Bytes, Error>>);
+
 struct BroadcasterInner {
-    clients: HashMap<u64, (Sender<Result<Bytes, Error>>, Receiver<Result<Bytes, Error>>)>,
+    clients: HashMap<u64, Channel>,
 }
 
 impl Broadcaster {
     pub fn create() -> Data<Self> {
-        let me = Data::new(Broadcaster {
+        Data::new(Broadcaster {
             inner: Mutex::new(BroadcasterInner {
                 clients: HashMap::new(),
             }),
-        });
-
-        me
+        })
     }
 
     pub fn close_sender(&self, key: u64) {
@@ -64,7 +64,7 @@ impl Broadcaster {
 
         let inner = self.inner.lock().unwrap();
         if let Some((sender, _)) = inner.clients.get(&key) {
-            if let Err(_) = sender.send(Ok(msg.clone())) {
+            if sender.send(Ok(msg.clone())).is_err() {
                 println!("ERROR");
             }
         } else {
@@ -1,37 +1,50 @@
 use actix_web::{
     http::header::{self, ContentType}, middleware,
-    web::{self, Data, Path},
+    web::{self, Data, Path, Query},
     App, HttpResponse, HttpServer, Responder,
 };
 
 mod broadcast;
 use broadcast::Broadcaster;
 
+mod preview;
+use preview::Previews;
+
 use raytracer::camera::Camera;
 use raytracer::vec3::Vec3;
+use raytracer::settings::RenderSettings;
+use raytracer::denoise::Denoising;
+use raytracer::progressive::ProgressiveRenderer;
+use raytracer::observer::{ RenderObserver, RenderProgress };
+use raytracer::cancel::{ CancellationToken, RenderStatus };
+use raytracer::output::{ ImageOutput, OutputFormat, OutputError };
 use raytracer::{ random_scene };
 
-use image::{RgbImage, ImageOutputFormat};
-use std::io::Cursor;
-use std::sync::{Arc, Mutex};
+use serde::Deserialize;
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
     let data = Broadcaster::create();
+    let previews = Previews::create();
 
     log::info!("starting HTTP server at http://localhost:8080");
 
     HttpServer::new(move || {
         App::new()
             .app_data(data.clone())
+            .app_data(previews.clone())
             .wrap(middleware::Logger::default())
             .route("/", web::get().to(index))
             .route("/render-event/{id}", web::get().to(new_client))
             .route("/render/{id}", web::get().to(broadcast))
+            .route("/preview/{id}", web::get().to(preview))
             .route("/size", web::get().to(size))
     })
+    // Without this a client that hangs up is only noticed once the response
+    // is written
+    .h1_allow_half_closed(false)
     .bind(("0.0.0.0", 8080))?
         .run()
         .await
@@ -59,14 +72,103 @@ async fn size(broadcaster: Data<Broadcaster>) -> impl Responder {
         .body(test)
 }
 
-async fn broadcast(id: Path<u64>, broadcaster: Data<Broadcaster>) -> impl Responder {
+// Image of the passes finished so far of a running render
+async fn preview(id: Path<u64>, previews: Data<Previews>) -> impl Responder {
+    match previews.get(*id) {
+        Some(png) => HttpResponse::Ok()
+            .content_type(ContentType::png())
+            .body(png),
+        None => HttpResponse::NotFound().finish(),
+    }
+}
+
+// Forwards render progress to the event stream of a render
+struct ProgressEvents<'a> {
+    id: u64,
+    broadcaster: &'a Broadcaster,
+}
+
+impl RenderObserver for ProgressEvents<'_> {
+    fn progress(&self, progress: &RenderProgress) {
+        let eta = progress.eta().map_or(String::new(), |eta| format!(" ({}s left)", eta.as_secs()));
+        self.broadcaster.send(self.id, &format!("progress {:.0}%{}", progress.fraction() * 100., eta));
+    }
+}
+
+// Cancels the token when dropped
+struct CancelOnDrop(CancellationToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+// Query of /render/{id}, e.g. ?format=jpeg&quality=80&denoise=true
+#[derive(Deserialize)]
+struct RenderQuery {
+    // File extension of the format, png by default
+    format: Option<String>,
+    // JPEG quality from 1 to 100
+    quality: Option<u8>,
+    // Filters the noise out of the finished image and the previews, off by
+    // default
+    denoise: Option<bool>,
+}
+
+impl RenderQuery {
+    fn image_output(&self) -> Option<ImageOutput> {
+        let format = match &self.format {
+            Some(format) => OutputFormat::from_extension(format)?,
+            None => OutputFormat::Png,
+        };
+
+        let output = ImageOutput::new(format);
+        Some(match self.quality {
+            Some(quality) => output.with_jpeg_quality(quality),
+            None => output,
+        })
+    }
+}
+
+enum RenderError {
+    Cancelled,
+    Output(OutputError),
+}
+
+async fn broadcast(id: Path<u64>, query: Query<RenderQuery>, broadcaster: Data<Broadcaster>, previews: Data<Previews>)
+                   -> impl Responder {
+    let id = *id;
+    let Some(output) = query.image_output() else {
+        return HttpResponse::BadRequest().body("unknown image format");
+    };
+    let denoise = query.denoise.unwrap_or(false);
+    let cancel = CancellationToken::new();
+    // actix drops this handler when the client disconnects (see
+    // h1_allow_half_closed), which stops the render instead of letting it
+    // use every core for nobody
+    let _cancel_on_drop = CancelOnDrop(cancel.clone());
+
+    match web::block(move || render(id, &broadcaster, &previews, &cancel, &output, denoise)).await {
+        Ok(Ok(image)) => HttpResponse::Ok()
+            .content_type(output.format.mime_type())
+            .body(image),
+        Ok(Err(RenderError::Output(error))) => HttpResponse::InternalServerError().body(error.to_string()),
+        _ => HttpResponse::ServiceUnavailable().body("render cancelled"),
+    }
+}
+
+// Renders the random scene, previews are always PNG
+fn render(id: u64, broadcaster: &Broadcaster, previews: &Previews, cancel: &CancellationToken, output: &ImageOutput,
+          denoise: bool) -> Result<Vec<u8>, RenderError> {
     let aspect_ratio = 3.0 / 2.0;
     let image_width = 900;
     let image_height = (image_width as f64 / aspect_ratio) as usize;
     let samples_per_pixel = 10;
     let max_depth = 50;
 
-    let world = random_scene(10);
+    let mut world = random_scene(10);
+    world.build_bvh();
 
     let lookfrom = Vec3::new(13., 2., 3.);
     let lookat = Vec3::new(0., 0., 0.);
@@ -76,26 +178,35 @@ async fn broadcast(id: Path<u64>, broadcaster: Data<Broadcaster>) -> impl Respon
     let aperture = 0.1;
     let camera = Camera::new(lookfrom, lookat, vup, 20., aspect_ratio, aperture, dist_to_focus);
 
-    let integer = Arc::new(Mutex::new(0));
-
-    broadcaster.new_connection(*id);
-
-    let buffer = raytracer::raytrace_buffer(image_width, image_height, samples_per_pixel, 
-                                            max_depth, &world, &camera, 
-                                            Some(&|_: String| {
-                                                let mut lock = integer.lock().unwrap();
-                                                *lock += 1;
-                                                let t = *lock;
-                                                if t % 1000 == 0 { broadcaster.send(*id, &t.to_string()); }
-                                            }));
-
-    broadcaster.close_sender(*id);
-
-    let img = RgbImage::from_vec(image_width as u32, image_height as u32, buffer).unwrap();
-    let mut w = Cursor::new(Vec::new());
-    img.write_to(&mut w, ImageOutputFormat::Png).unwrap();
-
-    HttpResponse::Ok()
-        .content_type(ContentType::png())
-        .body(w.into_inner())
+    broadcaster.new_connection(id);
+
+    // Every pass leaves a less noisy preview for /preview/{id}
+    let mut settings = RenderSettings::new(image_width, image_height, samples_per_pixel, max_depth);
+    // 10 samples per pixel are noisy, but denoising blurs fine detail
+    if denoise {
+        settings.denoise = Some(Denoising::default());
+    }
+    let events = ProgressEvents { id, broadcaster };
+    let preview_output = ImageOutput::new(OutputFormat::Png);
+    let mut renderer = ProgressiveRenderer::new(&settings, &world, &camera)
+        .with_observer(&events)
+        .with_cancellation(cancel);
+    while renderer.render_pass() {
+        match preview_output.encode(&renderer.framebuffer(), &settings.tone_mapping) {
+            Ok(png) => previews.set(id, png),
+            Err(error) => log::warn!("preview of render {}: {}", id, error),
+        }
+        broadcaster.send(id, &format!("pass {}", renderer.passes()));
+    }
+    // Finished and cancelled renders are not running anymore
+    previews.remove(id);
+
+    let cancelled = renderer.status() == RenderStatus::Cancelled;
+    broadcaster.send(id, if cancelled { "cancelled" } else { "done" });
+    broadcaster.close_sender(id);
+
+    if cancelled {
+        return Err(RenderError::Cancelled);
+    }
+    output.encode(&renderer.framebuffer(), &settings.tone_mapping).map_err(RenderError::Output)
 }
@@ -0,0 +1,29 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use actix_web::web::Data;
+
+// Latest PNG of every running progressive render, by render id
+pub struct Previews {
+    inner: Mutex<HashMap<u64, Vec<u8>>>,
+}
+
+impl Previews {
+    pub fn create() -> Data<Self> {
+        Data::new(Previews {
+            inner: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn set(&self, key: u64, png: Vec<u8>) {
+        self.inner.lock().unwrap().insert(key, png);
+    }
+
+    pub fn get(&self, key: u64) -> Option<Vec<u8>> {
+        self.inner.lock().unwrap().get(&key).cloned()
+    }
+
+    pub fn remove(&self, key: u64) {
+        self.inner.lock().unwrap().remove(&key);
+    }
+}